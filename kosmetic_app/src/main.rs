@@ -1,11 +1,32 @@
 use crossbeam_channel::*;
 use std::time::Duration;
 
-use kosmetic_zx::memory::*;
+use std::path::PathBuf;
+
 use kosmetic_zx::bus::*;
-use kosmetic_zx::cpu::*;
-use kosmetic_zx::clock::Clock;
-use kosmetic_zx::ula::Ula;
+use kosmetic_zx::autotype::AutoTyper;
+use kosmetic_zx::blend::FrameBlender;
+use kosmetic_zx::capture::{CaptureFormat, Recorder};
+use kosmetic_zx::filter::{self, Filter};
+use kosmetic_zx::frame::{Frame, Image};
+use kosmetic_zx::machine::{Machine, MachineConfig};
+use kosmetic_zx::memory::fill::RamFill;
+use kosmetic_zx::memory::rom;
+use kosmetic_zx::palette::Palette;
+use kosmetic_zx::screenshot;
+
+#[cfg(feature = "sdl")]
+use {
+    kosmetic_zx::controller::{ControllerEvent, ControllerMap, Controllers},
+    kosmetic_zx::common::Held,
+    kosmetic_zx::joystick::{JoyInput, JoystickInterface},
+    kosmetic_zx::keyboard::{self, KeyboardMatrix, SpecKey},
+    kosmetic_zx::keymap::{KeyMap, SmartKeyboard},
+    kosmetic_zx::mouse,
+    kosmetic_zx::video::{DisplayConfig, VideoLayer},
+    sdl2::event::{Event, WindowEvent},
+    sdl2::keyboard::{Keycode, Mod, Scancode},
+};
 
 #[cfg(feature = "tracing")]
 fn init_logging() {
@@ -20,58 +41,499 @@ fn init_logging() {
 #[cfg(not(feature = "tracing"))]
 fn init_logging() {}
 
-fn check_add_device(msg: BusMessage) {
-    match msg {
-        BusMessage::AddDeviceOk => return,
-        _ => { panic!("Couldn't add device to bus") }
+fn cycle_border(bus: Sender<BusMessage>) {
+    std::thread::spawn(move || {
+        let border_channel = bounded(128);
+        let mut i = 1;
+
+        loop {
+            if bus.send(BusMessage::IOPut(0xFE, i, border_channel.0.clone())).is_err() {
+                break;
+            }
+            let bus_ret = border_channel.1.try_recv();
+
+            if bus_ret.is_ok() {
+                match bus_ret.unwrap() {
+                    BusMessage::IOWriteOk => {},
+                    _ => {
+                        println!("Failed to write border colour");
+                        break;
+                    }
+                }
+            }
+
+            std::thread::sleep(Duration::from_micros(100));
+            i += 1;
+            if i == 8 {
+                i = 0;
+            }
+        }
+    });
+}
+
+/// Everything from the command line that isn't part of the machine itself
+#[derive(Default)]
+struct Options {
+    headless: bool,
+    frame_limit: Option<u64>,
+    /// Where the last frame is saved as a PNG in headless mode, the .SCR goes next to it
+    screenshot: Option<PathBuf>,
+    /// Video file to record to, .y4m or .gif
+    record: Option<PathBuf>,
+    /// Also record the audio to a .wav next to the video
+    record_audio: bool,
+    palette: Palette,
+    filters: Vec<Filter>,
+    /// How many frames to average together, if any
+    blend: Option<usize>,
+    /// Typed in once the machine has started, after `type_delay` frames
+    autotype: Option<String>,
+    type_delay: u32,
+    #[cfg(feature = "sdl")]
+    display: DisplayConfig,
+    /// Translate PC keys and symbols through this, rather than mapping keys by position
+    #[cfg(feature = "sdl")]
+    keymap: Option<KeyMap>,
+    /// Host keys that work the joystick instead of the keyboard, only while a joystick interface is plugged in
+    #[cfg(feature = "sdl")]
+    joystick_keys: JoystickKeys,
+    #[cfg(feature = "sdl")]
+    joystick: bool,
+    #[cfg(feature = "sdl")]
+    mouse: bool,
+    #[cfg(feature = "sdl")]
+    controller_map: ControllerMap
+}
+
+impl Options {
+    fn start_recording(&self, frame: &Image) -> Option<Recorder> {
+        let path = self.record.as_ref()?;
+        let format = CaptureFormat::from_path(path).expect("Recordings must be .y4m or .gif");
+        let wav = if self.record_audio { Some(path.with_extension("wav")) } else { None };
+
+        Some(Recorder::new(path, format, wav.as_deref(), frame.width, frame.height).expect("Couldn't start recording"))
+    }
+
+    #[cfg(feature = "sdl")]
+    fn joystick_key(&self, keycode: Option<Keycode>) -> Option<JoyInput> {
+        if self.joystick { self.joystick_keys.get(keycode?) } else { None }
+    }
+
+    fn typer(&self) -> Option<AutoTyper> {
+        self.autotype.as_ref().map(|text| AutoTyper::new(text, self.type_delay))
+    }
+
+    fn blender(&self) -> Option<FrameBlender> {
+        self.blend.map(FrameBlender::new)
+    }
+
+    /// Everything between the ULA's frame and what ends up on screen or on disk
+    fn convert(&self, frame: &Frame, palette: &Palette, blender: &mut Option<FrameBlender>) -> Image {
+        let image = frame.to_image(palette);
+        let image = match blender {
+            Some(b) => b.blend(image, frame.number),
+            None => image
+        };
+        filter::apply_all(&self.filters, image)
     }
 }
 
-fn main() {
-    init_logging();
+fn record(recorder: &mut Option<Recorder>, image: &Image, frame: &Frame) {
+    if let Some(r) = recorder {
+        r.record(image, frame).expect("Couldn't record frame");
+    }
+}
+
+fn stop_recording(recorder: Option<Recorder>) {
+    if let Some(r) = recorder {
+        r.finish().expect("Couldn't finish recording");
+    }
+}
+
+fn type_frame(typer: &mut Option<AutoTyper>, machine: &Machine, frame: &Frame) {
+    if let Some(t) = typer {
+        if !t.frame(&machine.keyboard, frame.number) {
+            *typer = None;
+        }
+    }
+}
+
+fn run_headless(machine: &mut Machine, options: &Options) {
+    let mut frames = 0_u64;
+    let mut samples = 0_usize;
+    let mut recorder = None;
+    let mut blender = options.blender();
+    let mut typer = options.typer();
+
+    while let Ok(frame) = machine.frames.recv() {
+        type_frame(&mut typer, machine, &frame);
+        let image = options.convert(&frame, &options.palette, &mut blender);
+        if frames == 0 {
+            recorder = options.start_recording(&image);
+        }
+
+        record(&mut recorder, &image, &frame);
+        frames += 1;
+        samples += frame.samples.len();
+
+        if Some(frames) == options.frame_limit {
+            if let Some(png) = &options.screenshot {
+                // Blending and filters are for watching, the screenshot is the frame as the ULA drew it
+                screenshot::save_png(&frame.to_image(&options.palette), png).expect("Couldn't save PNG screenshot");
+                screenshot::save_scr(&machine.ula_ram, &png.with_extension("scr")).expect("Couldn't save .SCR screenshot");
+            }
+
+            machine.stop();
+            break;
+        }
+    }
+
+    stop_recording(recorder);
+
+    println!("Ran {} frames, {} audio samples", frames, samples);
+}
+
+/// Up, down, left, right and fire
+#[cfg(feature = "sdl")]
+#[derive(Debug, Clone)]
+struct JoystickKeys([Keycode; 5]);
+
+#[cfg(feature = "sdl")]
+impl Default for JoystickKeys {
+    fn default() -> Self {
+        JoystickKeys([Keycode::Up, Keycode::Down, Keycode::Left, Keycode::Right, Keycode::RCtrl])
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl JoystickKeys {
+    /// Five comma separated SDL key names, in the same order as the fields
+    fn parse(list: &str) -> Result<JoystickKeys, String> {
+        let keys = list.split(',')
+            .map(|name| Keycode::from_name(name.trim()).ok_or_else(|| format!("Unknown key \"{}\"", name)))
+            .collect::<Result<Vec<Keycode>, _>>()?;
+
+        keys.try_into().map(JoystickKeys).map_err(|_| "Expected up,down,left,right,fire".to_string())
+    }
+
+    fn get(&self, keycode: Keycode) -> Option<JoyInput> {
+        let inputs = [JoyInput::Up, JoyInput::Down, JoyInput::Left, JoyInput::Right, JoyInput::Fire];
+        self.0.iter().position(|k| *k == keycode).map(|i| inputs[i])
+    }
+}
+
+/// How host keys reach the Spectrum keyboard
+#[cfg(feature = "sdl")]
+enum KeyInput {
+    /// Each host key is the Spectrum key in the same place
+    Raw(Held<SpecKey>),
+    Smart(SmartKeyboard)
+}
+
+#[cfg(feature = "sdl")]
+impl KeyInput {
+    fn key_down(&mut self, matrix: &KeyboardMatrix, keycode: Option<Keycode>, scancode: Option<Scancode>, keymod: Mod) {
+        match self {
+            KeyInput::Raw(held) => {
+                if let Some(key) = scancode.and_then(keyboard::from_scancode) {
+                    held.press(matrix, key);
+                }
+            }
+            KeyInput::Smart(smart) => {
+                if let Some(keycode) = keycode {
+                    smart.key_down(&keycode.name(), keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD), matrix);
+                }
+            }
+        }
+    }
+
+    fn key_up(&mut self, matrix: &KeyboardMatrix, keycode: Option<Keycode>, scancode: Option<Scancode>) {
+        match self {
+            KeyInput::Raw(held) => {
+                if let Some(key) = scancode.and_then(keyboard::from_scancode) {
+                    held.release(matrix, key);
+                }
+            }
+            KeyInput::Smart(smart) => {
+                if let Some(keycode) = keycode {
+                    smart.key_up(&keycode.name(), matrix);
+                }
+            }
+        }
+    }
 
-    let bus = Bus::new();
+    fn release_all(&mut self, matrix: &KeyboardMatrix) {
+        match self {
+            KeyInput::Raw(held) => held.release_all(matrix),
+            KeyInput::Smart(smart) => smart.release_all(matrix)
+        }
+    }
+}
 
-    let cpuram = cpumem::CPURam::new();
-    let ularam = ulamem::ULARam::new();
-    let rom = rom::Rom::new([0;0x4000]);
+#[cfg(feature = "sdl")]
+/// Screenshots are of the frame itself in the current palette, without any blending or filters
+fn take_screenshot(machine: &Machine, frame: &Frame, palette: &Palette) {
+    let png = PathBuf::from(format!("kosmetic-{}.png", frame.number));
+    let scr = png.with_extension("scr");
 
-    let ula_clock = Ula::new(Some(()), bus.clone());
-    let (cpu_clock, _) = bounded(128);
+    match screenshot::save_png(&frame.to_image(palette), &png).and_then(|_| screenshot::save_scr(&machine.ula_ram, &scr)) {
+        Ok(_) => println!("Saved {} and {}", png.display(), scr.display()),
+        Err(e) => println!("Couldn't save screenshot: {}", e)
+    }
+}
 
-    let bus_channel = bounded(128);
+#[cfg(feature = "sdl")]
+fn run_sdl(machine: &mut Machine, options: &Options) {
+    let first_frame = machine.frames.recv().expect("The ULA stopped before producing a frame");
+    // F9 cycles through the presets, and the palette from the command line if it's a user one
+    let mut palettes = Palette::presets();
+    let mut palette = palettes.iter().position(|p| *p == options.palette).unwrap_or_else(|| {
+        palettes.push(options.palette.clone());
+        palettes.len() - 1
+    });
 
-    bus.send(BusMessage::AddDevice(cpuram, bus_channel.0.clone())).unwrap();
-    check_add_device(bus_channel.1.recv().unwrap());
-    bus.send(BusMessage::AddDevice(ularam, bus_channel.0.clone())).unwrap();
-    check_add_device(bus_channel.1.recv().unwrap());
-    bus.send(BusMessage::AddDevice(rom, bus_channel.0.clone())).unwrap();
-    check_add_device(bus_channel.1.recv().unwrap());
-    bus.send(BusMessage::AddDevice(ula_clock.1, bus_channel.0.clone())).unwrap();
-    check_add_device(bus_channel.1.recv().unwrap());
+    let mut blender = options.blender();
+    let first_image = options.convert(&first_frame, &palettes[palette], &mut blender);
+    let mut video_layer = VideoLayer::new(first_image.width as u32, first_image.height as u32, options.display.clone());
+    video_layer.present(&first_image);
 
-    let _clock = Clock::new(cpu_clock.clone(),ula_clock.0.clone(), ula_clock.2);
+    let mut recorder = options.start_recording(&first_image);
+    record(&mut recorder, &first_image, &first_frame);
 
-    let mut i = 1;
+    let mut frames = 1_u64;
+    let mut keys = match &options.keymap {
+        Some(map) => KeyInput::Smart(SmartKeyboard::new(map.clone())),
+        None => KeyInput::Raw(Held::new())
+    };
+    let mut joystick_keys = Held::new();
+    let mut typer = options.typer();
+    let controller_sub_sys = video_layer.controller_sub_sys.lock().unwrap().clone();
+    let mut controllers = Controllers::new(controller_sub_sys, options.controller_map.clone());
+    // F10 lets go of the pointer, clicking in the window grabs it again
+    video_layer.set_mouse_captured(options.mouse);
+    // The click that grabbed the pointer, its button up isn't for the Spectrum either
+    let mut capture_click = None;
 
-    loop {
-        bus.send(BusMessage::IOPut(0xFE, i, bus_channel.0.clone())).unwrap();
-        let bus_ret = bus_channel.1.try_recv();
+    while let Ok(frame) = machine.frames.recv() {
+        type_frame(&mut typer, machine, &frame);
+        let image = options.convert(&frame, &palettes[palette], &mut blender);
+        video_layer.present(&image);
+        record(&mut recorder, &image, &frame);
+        frames += 1;
 
-        if bus_ret.is_ok() {
-            match bus_ret.unwrap() {
-                BusMessage::IOWriteOk => {},
-                _ => {
-                    println!("Failed to write border colour");
-                    break;
+        let mut quit = Some(frames) == options.frame_limit;
+        let events: Vec<Event> = video_layer.event_pump.lock().unwrap().poll_iter().collect();
+        for event in events {
+            match controllers.handle_event(&event, &machine.joystick, &machine.keyboard) {
+                ControllerEvent::Ignored => {}
+                ControllerEvent::Handled => continue,
+                ControllerEvent::Connected(name) => {
+                    println!("Controller connected: {}", name);
+                    continue;
+                }
+                ControllerEvent::Disconnected(name) => {
+                    println!("Controller disconnected: {}", name);
+                    continue;
+                }
+                ControllerEvent::OpenFailed(which, e) => {
+                    println!("Couldn't open controller {}: {}", which, e);
+                    continue;
                 }
             }
+
+            match event {
+                Event::Quit {..} => quit = true,
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => take_screenshot(machine, &frame, &palettes[palette]),
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => video_layer.toggle_fullscreen(),
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    palette = (palette + 1) % palettes.len();
+                    println!("Palette: {}", palettes[palette].name);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } if options.mouse => {
+                    video_layer.set_mouse_captured(false);
+                    machine.mouse.release_all();
+                }
+                Event::KeyDown { keycode, scancode, keymod, repeat: false, .. } => {
+                    // Anything typed now would be mixed into the text, so the user taking over stops it
+                    if let Some(mut t) = typer.take() {
+                        t.cancel(&machine.keyboard);
+                    }
+                    match options.joystick_key(keycode) {
+                        Some(input) => joystick_keys.press(&machine.joystick, input),
+                        None => keys.key_down(&machine.keyboard, keycode, scancode, keymod)
+                    }
+                }
+                Event::KeyUp { keycode, scancode, .. } => match options.joystick_key(keycode) {
+                    Some(input) => joystick_keys.release(&machine.joystick, input),
+                    None => keys.key_up(&machine.keyboard, keycode, scancode)
+                },
+                Event::MouseMotion { xrel, yrel, .. } if video_layer.is_mouse_captured() => machine.mouse.move_by(xrel, -yrel),
+                Event::MouseButtonDown { mouse_btn, .. } if options.mouse => {
+                    if !video_layer.is_mouse_captured() {
+                        video_layer.set_mouse_captured(true);
+                        capture_click = Some(mouse_btn);
+                    } else if let Some(button) = mouse::from_sdl_button(mouse_btn) {
+                        machine.mouse.press(button);
+                    }
+                }
+                Event::MouseButtonUp { mouse_btn, .. } if options.mouse => {
+                    if capture_click == Some(mouse_btn) {
+                        capture_click = None;
+                    } else if let Some(button) = mouse::from_sdl_button(mouse_btn) {
+                        machine.mouse.release(button);
+                    }
+                }
+                // The key ups go to whichever window has focus now, so don't leave anything held down
+                Event::Window { win_event: WindowEvent::FocusLost, .. } => {
+                    keys.release_all(&machine.keyboard);
+                    joystick_keys.release_all(&machine.joystick);
+                }
+                _ => {}
+            }
+        }
+
+        if quit {
+            machine.stop();
+            break;
         }
+    }
+
+    stop_recording(recorder);
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(_: &mut Machine, _: &Options) {
+    panic!("Built without the sdl feature, only --headless is available");
+}
+
+fn main() {
+    init_logging();
 
-        std::thread::sleep(Duration::from_micros(100));
-        i += 1;
-        if i == 8 {
-            i = 0;
+    let mut config = MachineConfig::default();
+    let mut options = Options {
+        headless: cfg!(not(feature = "sdl")),
+        // Long enough for the 48K ROM to clear RAM and show the copyright message
+        type_delay: 100,
+        ..Options::default()
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ram-fill" => {
+                config.ram_fill = args.next().expect("--ram-fill needs a policy").parse()
+                    .expect("Couldn't parse RAM fill policy");
+                if let RamFill::Random(_) = config.ram_fill {
+                    println!("RAM fill: {}", config.ram_fill);
+                }
+            }
+            "--headless" => options.headless = true,
+            "--ulaplus" => config.ulaplus = true,
+            "--joystick" => {
+                config.joystick = args.next().expect("--joystick needs an interface").parse()
+                    .expect("Couldn't parse joystick interface");
+            }
+            "--mouse" => config.mouse = true,
+            "--cartridge" => {
+                let path = PathBuf::from(args.next().expect("--cartridge needs a file"));
+                config.cartridge = Some(rom::load_cartridge(&path).expect("Couldn't load cartridge"));
+            }
+            "--timex" => config.ula.timex = true,
+            "--border" => {
+                config.ula.border = args.next().expect("--border needs a size").parse()
+                    .expect("Couldn't parse border size");
+            }
+            "--frames" => {
+                options.frame_limit = Some(args.next().expect("--frames needs a count").parse()
+                    .expect("Couldn't parse frame count"));
+            }
+            "--screenshot" => {
+                options.screenshot = Some(PathBuf::from(args.next().expect("--screenshot needs a path")));
+            }
+            "--record" => {
+                options.record = Some(PathBuf::from(args.next().expect("--record needs a path")));
+            }
+            "--record-audio" => options.record_audio = true,
+            "--palette" => {
+                options.palette = Palette::find(&args.next().expect("--palette needs a preset name or file"))
+                    .expect("Couldn't load palette");
+            }
+            "--filter" => {
+                // Comma separated and run in order, e.g. scale2x,scanlines
+                for name in args.next().expect("--filter needs a list of filters").split(',') {
+                    options.filters.push(name.parse().expect("Couldn't parse filter"));
+                }
+            }
+            "--type" => {
+                // \n in the text is ENTER, so whole commands fit on the command line
+                options.autotype = Some(args.next().expect("--type needs some text").replace("\\n", "\n"));
+            }
+            "--type-delay" => {
+                options.type_delay = args.next().expect("--type-delay needs a frame count").parse()
+                    .expect("Couldn't parse type delay");
+            }
+            "--blend" => {
+                let depth = args.next().expect("--blend needs a frame count").parse()
+                    .expect("Couldn't parse blend frame count");
+                assert!((2..=3).contains(&depth), "--blend must be 2 or 3");
+                options.blend = Some(depth);
+            }
+            #[cfg(feature = "sdl")]
+            "--scale" => {
+                options.display.scale = args.next().expect("--scale needs a factor").parse()
+                    .expect("Couldn't parse scale factor");
+                assert!((2..=6).contains(&options.display.scale), "--scale must be between 2 and 6");
+            }
+            #[cfg(feature = "sdl")]
+            "--fullscreen" => options.display.fullscreen = true,
+            #[cfg(feature = "sdl")]
+            "--pal-aspect" => options.display.pal_aspect = true,
+            #[cfg(feature = "sdl")]
+            "--keys" => {
+                options.keymap = match args.next().expect("--keys needs raw or smart").as_str() {
+                    "raw" => None,
+                    "smart" => Some(KeyMap::smart()),
+                    mode => panic!("Unknown key mode {}", mode)
+                };
+            }
+            #[cfg(feature = "sdl")]
+            "--joy-keys" => {
+                options.joystick_keys = JoystickKeys::parse(&args.next().expect("--joy-keys needs up,down,left,right,fire"))
+                    .expect("Couldn't parse joystick keys");
+            }
+            #[cfg(feature = "sdl")]
+            "--pad-map" => {
+                let path = PathBuf::from(args.next().expect("--pad-map needs a file"));
+                options.controller_map = ControllerMap::load(&path).expect("Couldn't load controller map");
+            }
+            #[cfg(feature = "sdl")]
+            "--keymap" => {
+                let path = PathBuf::from(args.next().expect("--keymap needs a file"));
+                options.keymap = Some(KeyMap::load(&path).expect("Couldn't load key map"));
+            }
+            _ => panic!("Unknown argument {}", arg)
         }
     }
+
+    // The screenshot is taken of the last frame, so there has to be one
+    assert!(options.screenshot.is_none() || options.frame_limit.is_some(), "--screenshot needs --frames");
+
+    #[cfg(feature = "sdl")]
+    {
+        options.joystick = config.joystick != JoystickInterface::None;
+        options.mouse = config.mouse;
+    }
+
+    // Only a live display can skip frames it's too slow for, recordings and headless runs see all of them
+    config.ula.wait_for_frames = options.headless || options.record.is_some();
+
+    let mut machine = Machine::new(config);
+
+    // The border demo would fight whatever a headless run is trying to capture
+    if options.headless {
+        run_headless(&mut machine, &options);
+    } else {
+        cycle_border(machine.bus.clone());
+        run_sdl(&mut machine, &options);
+    }
 }
@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+use crate::keyboard::{KeyboardMatrix, SpecKey};
+use crate::keyboard::SpecKey::*;
+
+/// The ROM scans the keyboard once per frame, a key has to be seen on at least one scan to register
+static HOLD_FRAMES: u64 = 3;
+/// The ROM won't take the same key again until it's been up for 5 scans
+static GAP_FRAMES: u64 = 6;
+
+/// How a keyword is entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    /// One key in K mode, so only at the start of a statement
+    Command,
+    /// A key in E mode (CAPS SHIFT + SYMBOL SHIFT first)
+    Extended,
+    /// SYMBOL SHIFT + key in E mode
+    ExtendedSymbol,
+    /// SYMBOL SHIFT + key in K or L mode
+    Symbol
+}
+
+static KEYWORDS: &[(&str, Entry, SpecKey)] = &[
+    ("PLOT", Entry::Command, Q), ("DRAW", Entry::Command, W), ("REM", Entry::Command, E),
+    ("RUN", Entry::Command, R), ("RANDOMIZE", Entry::Command, T), ("RETURN", Entry::Command, Y),
+    ("IF", Entry::Command, U), ("INPUT", Entry::Command, I), ("POKE", Entry::Command, O),
+    ("PRINT", Entry::Command, P), ("NEW", Entry::Command, A), ("SAVE", Entry::Command, S),
+    ("DIM", Entry::Command, D), ("FOR", Entry::Command, F), ("GO TO", Entry::Command, G),
+    ("GOTO", Entry::Command, G), ("GO SUB", Entry::Command, H), ("GOSUB", Entry::Command, H), ("LOAD", Entry::Command, J), ("LIST", Entry::Command, K),
+    ("LET", Entry::Command, L), ("COPY", Entry::Command, Z), ("CLEAR", Entry::Command, X),
+    ("CONTINUE", Entry::Command, C), ("CLS", Entry::Command, V), ("BORDER", Entry::Command, B),
+    ("NEXT", Entry::Command, N), ("PAUSE", Entry::Command, M),
+
+    ("SIN", Entry::Extended, Q), ("COS", Entry::Extended, W), ("TAN", Entry::Extended, E),
+    ("INT", Entry::Extended, R), ("RND", Entry::Extended, T), ("STR$", Entry::Extended, Y),
+    ("CHR$", Entry::Extended, U), ("CODE", Entry::Extended, I), ("PEEK", Entry::Extended, O),
+    ("TAB", Entry::Extended, P), ("READ", Entry::Extended, A), ("RESTORE", Entry::Extended, S),
+    ("DATA", Entry::Extended, D), ("SGN", Entry::Extended, F), ("ABS", Entry::Extended, G),
+    ("SQR", Entry::Extended, H), ("VAL", Entry::Extended, J), ("LEN", Entry::Extended, K),
+    ("USR", Entry::Extended, L), ("LN", Entry::Extended, Z), ("EXP", Entry::Extended, X),
+    ("LPRINT", Entry::Extended, C), ("LLIST", Entry::Extended, V), ("BIN", Entry::Extended, B),
+    ("INKEY$", Entry::Extended, N), ("PI", Entry::Extended, M),
+
+    ("ASN", Entry::ExtendedSymbol, Q), ("ACS", Entry::ExtendedSymbol, W), ("ATN", Entry::ExtendedSymbol, E),
+    ("VERIFY", Entry::ExtendedSymbol, R), ("MERGE", Entry::ExtendedSymbol, T), ("IN", Entry::ExtendedSymbol, I),
+    ("OUT", Entry::ExtendedSymbol, O), ("CIRCLE", Entry::ExtendedSymbol, H), ("VAL$", Entry::ExtendedSymbol, J),
+    ("SCREEN$", Entry::ExtendedSymbol, K), ("ATTR", Entry::ExtendedSymbol, L), ("BEEP", Entry::ExtendedSymbol, Z),
+    ("INK", Entry::ExtendedSymbol, X), ("PAPER", Entry::ExtendedSymbol, C), ("FLASH", Entry::ExtendedSymbol, V),
+    ("BRIGHT", Entry::ExtendedSymbol, B), ("OVER", Entry::ExtendedSymbol, N), ("INVERSE", Entry::ExtendedSymbol, M),
+    ("DEF FN", Entry::ExtendedSymbol, Num1), ("FN", Entry::ExtendedSymbol, Num2), ("LINE", Entry::ExtendedSymbol, Num3),
+    ("OPEN #", Entry::ExtendedSymbol, Num4), ("CLOSE #", Entry::ExtendedSymbol, Num5), ("MOVE", Entry::ExtendedSymbol, Num6),
+    ("ERASE", Entry::ExtendedSymbol, Num7), ("POINT", Entry::ExtendedSymbol, Num8), ("CAT", Entry::ExtendedSymbol, Num9),
+    ("FORMAT", Entry::ExtendedSymbol, Num0),
+
+    ("<=", Entry::Symbol, Q), ("<>", Entry::Symbol, W), (">=", Entry::Symbol, E),
+    ("AND", Entry::Symbol, Y), ("OR", Entry::Symbol, U), ("AT", Entry::Symbol, I),
+    ("STOP", Entry::Symbol, A), ("NOT", Entry::Symbol, S), ("STEP", Entry::Symbol, D),
+    ("TO", Entry::Symbol, F), ("THEN", Entry::Symbol, G)
+];
+
+/// Characters that are a SYMBOL SHIFT combination in L mode
+static SYMBOLS: &[(char, SpecKey)] = &[
+    ('!', Num1), ('@', Num2), ('#', Num3), ('$', Num4), ('%', Num5), ('&', Num6), ('\'', Num7), ('(', Num8),
+    (')', Num9), ('_', Num0), ('<', R), ('>', T), (';', O), ('"', P), ('^', H), ('-', J), ('+', K), ('=', L),
+    (':', Z), ('£', X), ('?', C), ('/', V), ('*', B), (',', N), ('.', M)
+];
+
+/// Characters that are a SYMBOL SHIFT combination in E mode
+static EXTENDED_SYMBOLS: &[(char, SpecKey)] = &[
+    ('[', Y), (']', U), ('~', A), ('|', S), ('\\', D), ('{', F), ('}', G), ('©', P)
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Press(Vec<SpecKey>),
+    Release
+}
+
+/// Types text into the keyboard matrix a frame at a time, as if someone was at the keyboard
+///
+/// Keywords in the text (in capitals) are entered the way the 48K editor wants them, so `RANDOMIZE USR 32768`
+/// is T, then E mode and L, then the digits. Spaces next to keywords are dropped as the ROM adds its own.
+/// Commands at the start of a statement can be in any case since K mode can only type a keyword there, and an
+/// assignment without a command gets the LET the 48K insists on. Nothing in a string or after a REM is a keyword
+#[derive(Debug, Clone)]
+pub struct AutoTyper {
+    steps: VecDeque<Step>,
+    /// `Frame::number` when the next step is due, set from `delay` by the first frame
+    next: Option<u64>,
+    /// Frames to wait before the first key, to let the ROM finish starting up
+    delay: u32,
+    /// The keys the current step is holding down
+    held: Vec<SpecKey>
+}
+
+impl AutoTyper {
+    /// `\n` is ENTER, anything that can't be typed on a 48K is skipped
+    pub fn new(text: &str, delay: u32) -> AutoTyper {
+        AutoTyper {
+            steps: Self::tokenise(text),
+            next: None,
+            delay,
+            held: Vec::new()
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.steps.is_empty() && self.held.is_empty()
+    }
+
+    /// Call once per frame with its `Frame::number`, returns false once everything has been typed
+    ///
+    /// Keys are held for a number of emulated frames, so skipped frames don't make them any shorter
+    pub fn frame(&mut self, matrix: &KeyboardMatrix, number: u64) -> bool {
+        let next = *self.next.get_or_insert(number + self.delay as u64);
+        if number < next {
+            return true;
+        }
+
+        for key in self.held.drain(..) {
+            matrix.release(key);
+        }
+
+        match self.steps.pop_front() {
+            Some(Step::Press(keys)) => {
+                keys.iter().for_each(|k| matrix.press(*k));
+                self.held = keys;
+                self.next = Some(number + HOLD_FRAMES);
+                true
+            }
+            Some(Step::Release) => {
+                self.next = Some(number + GAP_FRAMES);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Lets go of anything still held and forgets the rest of the text
+    pub fn cancel(&mut self, matrix: &KeyboardMatrix) {
+        for key in self.held.drain(..) {
+            matrix.release(key);
+        }
+        self.steps.clear();
+    }
+
+    fn tokenise(text: &str) -> VecDeque<Step> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut steps = VecDeque::new();
+        let mut press = |keys: &[SpecKey]| {
+            steps.push_back(Step::Press(keys.to_vec()));
+            steps.push_back(Step::Release);
+        };
+
+        let mut i = 0;
+        // K mode, where a single key is a command, lasts until the first thing after the line number
+        let mut statement_start = true;
+        let mut in_string = false;
+        // The rest of the line after a REM is typed as it is, like a string
+        let mut in_rem = false;
+        let mut pending_space = false;
+
+        while i < chars.len() {
+            let c = chars[i];
+            let literal = in_string || in_rem;
+
+            if !literal {
+                if let Some((word, entry, key)) = Self::keyword_at(&chars, i, statement_start) {
+                    match entry {
+                        Entry::Command => press(&[key]),
+                        Entry::Extended => {
+                            press(&[CapsShift, SymbolShift]);
+                            press(&[key]);
+                        }
+                        Entry::ExtendedSymbol => {
+                            press(&[CapsShift, SymbolShift]);
+                            press(&[SymbolShift, key]);
+                        }
+                        Entry::Symbol => press(&[SymbolShift, key])
+                    }
+
+                    statement_start = word == "THEN";
+                    in_rem = word == "REM";
+                    pending_space = false;
+                    i += word.chars().count();
+                    while i < chars.len() && chars[i] == ' ' {
+                        i += 1;
+                    }
+                    continue;
+                }
+
+                if c == ' ' {
+                    pending_space = true;
+                    i += 1;
+                    continue;
+                }
+            }
+
+            // K mode would turn the letter into a command, so this is an assignment missing its LET
+            if statement_start && !literal && c.is_ascii_alphabetic() {
+                press(&[L]);
+                pending_space = false;
+            }
+
+            if pending_space {
+                press(&[Space]);
+                pending_space = false;
+            }
+
+            match c {
+                '\n' => {
+                    press(&[Enter]);
+                    statement_start = true;
+                    in_string = false;
+                    in_rem = false;
+                }
+                ' ' => press(&[Space]),
+                // A line number keeps the editor in K mode
+                '0'..='9' => press(&[c.to_string().parse().unwrap()]),
+                'a'..='z' => {
+                    if let Ok(key) = c.to_string().parse() {
+                        press(&[key]);
+                    }
+                    statement_start = false;
+                }
+                'A'..='Z' => {
+                    if let Ok(key) = c.to_string().parse() {
+                        press(&[CapsShift, key]);
+                    }
+                    statement_start = false;
+                }
+                _ => {
+                    if let Some((_, key)) = EXTENDED_SYMBOLS.iter().find(|(s, _)| *s == c) {
+                        press(&[CapsShift, SymbolShift]);
+                        press(&[SymbolShift, *key]);
+                    } else if let Some((_, key)) = SYMBOLS.iter().find(|(s, _)| *s == c) {
+                        press(&[SymbolShift, *key]);
+                    }
+                    if c == '"' {
+                        in_string = !in_string;
+                    }
+                    statement_start = c == ':' && !in_string && !in_rem;
+                }
+            }
+
+            i += 1;
+        }
+
+        steps
+    }
+
+    /// The longest keyword starting at `i`, commands only count at the start of a statement where they can be in
+    /// any case
+    fn keyword_at(chars: &[char], i: usize, statement_start: bool) -> Option<(&'static str, Entry, SpecKey)> {
+        let is_word_char = |c: &char| c.is_ascii_alphanumeric() || *c == '$';
+        let after_word = i > 0 && chars[i - 1].is_ascii_alphanumeric();
+
+        KEYWORDS.iter()
+            .filter(|(_, entry, _)| statement_start || *entry != Entry::Command)
+            .filter(|(word, entry, _)| {
+                let len = word.chars().count();
+                // Words have to stand on their own, so TO doesn't match TOTAL or POTATO, symbols like <= don't care
+                let is_word = word.starts_with(|c: char| c.is_ascii_alphabetic());
+                let ignore_case = *entry == Entry::Command;
+                chars.len() >= i + len
+                    && chars[i..i + len].iter().zip(word.chars())
+                        .all(|(c, w)| if ignore_case { c.to_ascii_uppercase() == w } else { *c == w })
+                    && !(is_word && (after_word || chars.get(i + len).is_some_and(is_word_char)))
+            })
+            .max_by_key(|(word, _, _)| word.len())
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just the key combinations, the releases between them are always there
+    fn presses(text: &str) -> Vec<Vec<SpecKey>> {
+        AutoTyper::tokenise(text).into_iter()
+            .filter_map(|step| match step {
+                Step::Press(keys) => Some(keys),
+                Step::Release => None
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenises_basic() {
+        let extended = vec![CapsShift, SymbolShift];
+        let cases: &[(&str, Vec<Vec<SpecKey>>)] = &[
+            ("10 PRINT \"hi\"", vec![vec![Num1], vec![Num0], vec![P], vec![SymbolShift, P], vec![H], vec![I], vec![SymbolShift, P]]),
+            ("print 1", vec![vec![P], vec![Num1]]),
+            ("Border 2", vec![vec![B], vec![Num2]]),
+            ("10 x=1", vec![vec![Num1], vec![Num0], vec![L], vec![X], vec![SymbolShift, L], vec![Num1]]),
+            ("IF a THEN b=1", vec![vec![U], vec![A], vec![SymbolShift, G], vec![L], vec![B], vec![SymbolShift, L], vec![Num1]]),
+            ("GO TO 5", vec![vec![G], vec![Num5]]),
+            ("GOTO 5", vec![vec![G], vec![Num5]]),
+            ("gosub 5", vec![vec![H], vec![Num5]]),
+            ("GO SUB 5", vec![vec![H], vec![Num5]]),
+            ("RANDOMIZE USR 32768", vec![vec![T], extended.clone(), vec![L], vec![Num3], vec![Num2], vec![Num7], vec![Num6], vec![Num8]]),
+            ("PRINT a<=b", vec![vec![P], vec![A], vec![SymbolShift, Q], vec![B]]),
+            ("PRINT TOTAL", vec![vec![P], vec![CapsShift, T], vec![CapsShift, O], vec![CapsShift, T], vec![CapsShift, A], vec![CapsShift, L]]),
+            ("PRINT to", vec![vec![P], vec![T], vec![O]]),
+            ("INK 2", vec![extended.clone(), vec![SymbolShift, X], vec![Num2]]),
+            ("PRINT \"[~]\"", vec![vec![P], vec![SymbolShift, P], extended.clone(), vec![SymbolShift, Y], extended.clone(),
+                vec![SymbolShift, A], extended.clone(), vec![SymbolShift, U], vec![SymbolShift, P]]),
+            ("PRINT \"|\\{}©\"", vec![vec![P], vec![SymbolShift, P], extended.clone(), vec![SymbolShift, S], extended.clone(),
+                vec![SymbolShift, D], extended.clone(), vec![SymbolShift, F], extended.clone(), vec![SymbolShift, G],
+                extended.clone(), vec![SymbolShift, P], vec![SymbolShift, P]]),
+            ("CLS: BEEP 1,0", vec![vec![V], vec![SymbolShift, Z], extended.clone(), vec![SymbolShift, Z], vec![Num1], vec![SymbolShift, N], vec![Num0]]),
+            ("PRINT \"a b\"", vec![vec![P], vec![SymbolShift, P], vec![A], vec![Space], vec![B], vec![SymbolShift, P]]),
+            ("RUN\nLIST", vec![vec![R], vec![Enter], vec![K]]),
+            ("PRINT \"TO\"", vec![vec![P], vec![SymbolShift, P], vec![CapsShift, T], vec![CapsShift, O], vec![SymbolShift, P]]),
+            ("10 REM PRINT: x", vec![vec![Num1], vec![Num0], vec![E], vec![CapsShift, P], vec![CapsShift, R], vec![CapsShift, I],
+                vec![CapsShift, N], vec![CapsShift, T], vec![SymbolShift, Z], vec![Space], vec![X]]),
+            ("REM to\nx=1", vec![vec![E], vec![T], vec![O], vec![Enter], vec![L], vec![X], vec![SymbolShift, L], vec![Num1]]),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(&presses(text), expected, "typing {:?}", text);
+        }
+    }
+
+    #[test]
+    fn keys_are_timed_by_frame_number() {
+        let matrix = KeyboardMatrix::new();
+        let mut typer = AutoTyper::new("1", 2);
+
+        assert!(typer.frame(&matrix, 10));
+        assert!(!matrix.is_pressed(Num1));
+        assert!(typer.frame(&matrix, 12));
+        assert!(matrix.is_pressed(Num1));
+        assert!(typer.frame(&matrix, 14));
+        assert!(matrix.is_pressed(Num1));
+        // Frames 15 to 19 were skipped, the key has still been down long enough
+        assert!(typer.frame(&matrix, 20));
+        assert!(!matrix.is_pressed(Num1));
+        assert!(typer.frame(&matrix, 25));
+        assert!(!typer.frame(&matrix, 26));
+        assert!(typer.is_finished());
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+use crate::frame::Image;
+
+/// Averages each frame with the ones before it, so Gigascreen and 3-colour demos that flip screens every
+/// frame look like they do on a CRT
+#[derive(Debug, Clone)]
+pub struct FrameBlender {
+    depth: usize,
+    history: VecDeque<Image>,
+    /// `Frame::number` of the newest image in `history`
+    last: Option<u64>
+}
+
+impl FrameBlender {
+    /// `depth` is how many frames are averaged, 2 for Gigascreen and 3 for 3-colour modes
+    pub fn new(depth: usize) -> FrameBlender {
+        assert!((2..=3).contains(&depth), "Only 2 or 3 frames can be blended");
+
+        FrameBlender {
+            depth,
+            history: VecDeque::with_capacity(depth),
+            last: None
+        }
+    }
+
+    /// Until enough frames have come in, the average is over however many there are
+    ///
+    /// `number` is the image's `Frame::number`, the screens being flipped only pair up if none were skipped
+    pub fn blend(&mut self, image: Image, number: u64) -> Image {
+        // Anything of a different size is from before a mode change and can't be mixed in
+        if self.history.front().is_some_and(|i| i.width != image.width || i.height != image.height)
+            || self.last.is_some_and(|last| last + 1 != number) {
+            self.history.clear();
+        }
+        self.last = Some(number);
+
+        if self.history.len() == self.depth {
+            self.history.pop_front();
+        }
+        self.history.push_back(image);
+
+        let latest = self.history.back().unwrap();
+        let mut out = Image::new(latest.width, latest.height);
+        let count = self.history.len() as u32;
+
+        for (i, byte) in out.rgba.iter_mut().enumerate() {
+            let sum: u32 = self.history.iter().map(|image| image.rgba[i] as u32).sum();
+            *byte = ((sum + count / 2) / count) as u8;
+        }
+
+        out
+    }
+}
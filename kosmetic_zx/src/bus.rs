@@ -36,7 +36,8 @@ pub enum BusMessage {
     AddDevice(Sender<BusMessage>, Sender<BusMessage>),
     AddDeviceOk,
     GetRanges(Sender<BusMessage>),
-    RangesRet(Vec<Range>, Vec<Range>, Vec<Range>, Vec<Range>),
+    /// Memory read ranges, memory write ranges, I/O read ports, I/O write ports
+    RangesRet(Vec<Range>, Vec<Range>, Vec<Port>, Vec<Port>),
     IOGet(Address, Sender<BusMessage>),
     MemGet(Address, Sender<BusMessage>),
     IOPut(Address, Byte, Sender<BusMessage>),
@@ -51,18 +52,49 @@ pub enum BusMessage {
 #[derive(Debug, Clone)]
 pub struct Range(pub Address, pub Address);
 
+/// A partially decoded I/O port, the device answers every port address where `address & mask == value`
+///
+/// Unlike memory, I/O devices are sent the full port address since the undecoded lines often mean something
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+    pub mask: Address,
+    pub value: Address,
+}
+
+impl Port {
+    pub fn new(mask: Address, value: Address) -> Port {
+        Port { mask, value }
+    }
+
+    /// A port that only answers to exactly `address`
+    pub fn exact(address: Address) -> Port {
+        Port { mask: 0xFFFF, value: address }
+    }
+
+    pub fn matches(&self, address: Address) -> bool {
+        address & self.mask == self.value
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MapEntry {
     pub(crate) device: Sender<BusMessage>,
     pub(crate) range: Range,
 }
 
+#[derive(Debug, Clone)]
+pub struct PortEntry {
+    pub(crate) device: Sender<BusMessage>,
+    pub(crate) port: Port,
+}
+
 #[derive(Debug)]
 pub struct Bus {
     pub(crate) read_ranges: memMap<Address, MapEntry>,
     pub(crate) write_ranges: memMap<Address, MapEntry>,
-    pub(crate) io_read_ranges: memMap<Address, MapEntry>,
-    pub(crate) io_write_ranges: memMap<Address, MapEntry>,
+    /// Kept with the most fully decoded ports first, so they win over partial decodes that overlap them
+    pub(crate) io_read_ports: Vec<PortEntry>,
+    pub(crate) io_write_ports: Vec<PortEntry>,
     pub receiver: Receiver<BusMessage>
 }
 
@@ -75,41 +107,43 @@ impl Bus {
             let mut bus = Bus {
                 read_ranges: memMap::new(),
                 write_ranges: memMap::new(),
-                io_read_ranges: memMap::new(),
-                io_write_ranges: memMap::new(),
+                io_read_ports: Vec::new(),
+                io_write_ports: Vec::new(),
                 receiver: rx
             };
 
-            loop {
-                match bus.receiver.recv().unwrap() {
+            // Replies can fail when the requester has already given up, that's their problem not the bus's.
+            // Once every sender is gone the machine has been torn down, so the bus goes with it
+            while let Ok(msg) = bus.receiver.recv() {
+                match msg {
                     BusMessage::IOGet(a, s) => {
                         match bus.read(a,true) {
-                            Ok(b) => {s.send(BusMessage::IOReadOk(b)).unwrap()}
-                            Err(_) => {s.send(BusMessage::Err).unwrap()}
+                            Ok(b) => {let _ = s.send(BusMessage::IOReadOk(b));}
+                            Err(_) => {let _ = s.send(BusMessage::Err);}
                         }
                     }
                     BusMessage::MemGet(a, s) => {
                         match bus.read(a,false) {
-                            Ok(b) => {s.send(BusMessage::MemReadOk(b)).unwrap()}
-                            Err(_) => {s.send(BusMessage::Err).unwrap()}
+                            Ok(b) => {let _ = s.send(BusMessage::MemReadOk(b));}
+                            Err(_) => {let _ = s.send(BusMessage::Err);}
                         }
                     }
                     BusMessage::IOPut(a, b, s) => {
                         match bus.write(a, b, true) {
-                            Ok(_) => {s.send(BusMessage::IOWriteOk).unwrap()}
-                            Err(_) => {s.send(BusMessage::Err).unwrap()}
+                            Ok(_) => {let _ = s.send(BusMessage::IOWriteOk);}
+                            Err(_) => {let _ = s.send(BusMessage::Err);}
                         }
 
                     }
                     BusMessage::MemPut(a, b, s) => {
                         match bus.write(a, b, false) {
-                            Ok(_) => {s.send(BusMessage::MemWriteOk).unwrap()}
-                            Err(_) => {s.send(BusMessage::Err).unwrap()}
+                            Ok(_) => {let _ = s.send(BusMessage::MemWriteOk);}
+                            Err(_) => {let _ = s.send(BusMessage::Err);}
                         }
                     }
                     BusMessage::AddDevice(d,s) => {
                         bus.add_device(d);
-                        s.send(BusMessage::AddDeviceOk).unwrap();
+                        let _ = s.send(BusMessage::AddDeviceOk);
                     }
                     BusMessage::Err => {}
                     _ => {}
@@ -152,80 +186,128 @@ impl Bus {
             );
         }
 
-        for range in ranges.2 {
-            self.io_read_ranges.insert(
-                range.0,
-                MapEntry {
-                    device: device.clone(),
-                    range,
-                },
-            );
+        for port in ranges.2 {
+            self.io_read_ports.push(PortEntry {
+                device: device.clone(),
+                port,
+            });
         }
 
-        for range in ranges.3 {
-            self.io_write_ranges.insert(
-                range.0,
-                MapEntry {
-                    device: device.clone(),
-                    range,
-                },
-            );
+        for port in ranges.3 {
+            self.io_write_ports.push(PortEntry {
+                device: device.clone(),
+                port,
+            });
         }
+
+        self.io_read_ports.sort_by_key(|e| std::cmp::Reverse(e.port.mask.count_ones()));
+        self.io_write_ports.sort_by_key(|e| std::cmp::Reverse(e.port.mask.count_ones()));
+    }
+
+    fn port_device(ports: &[PortEntry], address: Address) -> Option<&Sender<BusMessage>> {
+        ports.iter().find(|e| e.port.matches(address)).map(|e| &e.device)
     }
 
     #[cfg_attr(feature = "trace-bus", instrument(name = "Write to bus", skip_all))]
     pub fn write(&mut self, address: Address, data: Byte, io_bus: bool) -> Result<(), ()> {
-        for (key, val) in if io_bus == true {
-            self.io_write_ranges.iter_mut()
+        let (tx,rx) = bounded(1);
+
+        // A device that has shut down (like the ULA after a clock stop) just fails the access
+        if io_bus {
+            let device = Self::port_device(&self.io_write_ports, address).ok_or(())?;
+            device.send(BusMessage::IOPut(address, data, tx)).map_err(|_| ())?;
         } else {
-            self.write_ranges.iter_mut()
-        } {
-            if address >= *key {
-                if address < val.range.1 {
-                    let (tx,rx) = bounded(1);
-                    val.device.send(if io_bus {
-                        BusMessage::IOPut(address - key, data, tx)
-                    } else {
-                        BusMessage::MemPut(address - key, data, tx)
-                    }).unwrap();
-                    let ret = rx.recv().unwrap();
-                    return match ret {
-                        BusMessage::IOWriteOk => Ok(()),
-                        BusMessage::MemWriteOk => Ok(()),
-                        _ => Err(())
-                    }
-                }
-            }
+            let (key, val) = self.write_ranges.iter()
+                .find(|(key, val)| address >= **key && address < val.range.1)
+                .ok_or(())?;
+            val.device.send(BusMessage::MemPut(address - key, data, tx)).map_err(|_| ())?;
+        }
+
+        match rx.recv() {
+            Ok(BusMessage::IOWriteOk) => Ok(()),
+            Ok(BusMessage::MemWriteOk) => Ok(()),
+            _ => Err(())
         }
-        Err(())
     }
 
     #[cfg_attr(feature = "trace-bus", instrument(name = "Read from bus", skip_all))]
     pub fn read(&self, address: Address, io_bus: bool) -> Result<Byte, ()> {
-        for (key, val) in if io_bus == true {
-            self.io_read_ranges.iter()
+        let (tx,rx) = bounded(1);
+
+        if io_bus {
+            let device = Self::port_device(&self.io_read_ports, address).ok_or(())?;
+            device.send(BusMessage::IOGet(address, tx)).map_err(|_| ())?;
         } else {
-            self.read_ranges.iter()
-        } {
-            if address >= *key {
-                if address < val.range.1 {
-                    let (tx,rx) = bounded(1);
-                    val.device.send(if io_bus {
-                        BusMessage::IOGet(address - key, tx)
-                    } else {
-                        BusMessage::MemGet(address - key, tx)
-                    }).unwrap();
-
-                    let ret = rx.recv().unwrap();
-
-                    return match ret {
-                        BusMessage::IOReadOk(data) => Ok(data),
-                        BusMessage::MemReadOk(data) => Ok(data),
-                        _ => Err(())
-                    }
+            let (key, val) = self.read_ranges.iter()
+                .find(|(key, val)| address >= **key && address < val.range.1)
+                .ok_or(())?;
+            val.device.send(BusMessage::MemGet(address - key, tx)).map_err(|_| ())?;
+        }
+
+        match rx.recv() {
+            Ok(BusMessage::IOReadOk(data)) => Ok(data),
+            Ok(BusMessage::MemReadOk(data)) => Ok(data),
+            _ => Err(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every read of its ports with `tag`
+    fn port_device(ports: Vec<Port>, tag: Byte) -> Sender<BusMessage> {
+        let (tx, rx) = bounded(128);
+        thread::spawn(move || {
+            while let Ok(msg) = rx.recv() {
+                match msg {
+                    BusMessage::IOGet(_, s) => { let _ = s.send(BusMessage::IOReadOk(tag)); },
+                    BusMessage::GetRanges(s) => { let _ = s.send(BusMessage::RangesRet(vec![], vec![], ports.clone(), vec![])); },
+                    _ => {}
                 }
             }
+        });
+        tx
+    }
+
+    fn add(bus: &Sender<BusMessage>, device: Sender<BusMessage>) {
+        let (tx, rx) = bounded(1);
+        bus.send(BusMessage::AddDevice(device, tx)).unwrap();
+        assert!(matches!(rx.recv().unwrap(), BusMessage::AddDeviceOk));
+    }
+
+    fn read(bus: &Sender<BusMessage>, port: Address) -> Option<Byte> {
+        let (tx, rx) = bounded(1);
+        bus.send(BusMessage::IOGet(port, tx)).unwrap();
+        match rx.recv().unwrap() {
+            BusMessage::IOReadOk(b) => Some(b),
+            _ => None
         }
-        Err(())
+    }
+
+    #[test]
+    fn port_matches_its_decoded_lines_only() {
+        let port = Port::new(0x0020, 0x0000);
+        assert!(port.matches(0x001F));
+        assert!(port.matches(0xFFDF));
+        assert!(!port.matches(0x003F));
+
+        assert!(Port::exact(0xBF3B).matches(0xBF3B));
+        assert!(!Port::exact(0xBF3B).matches(0xFF3B));
+    }
+
+    #[test]
+    fn the_most_fully_decoded_port_wins() {
+        let bus = Bus::new();
+        // Added least specific first, the order they're added in mustn't matter
+        add(&bus, port_device(vec![Port::new(0x0020, 0x0000)], 1));
+        add(&bus, port_device(vec![Port::new(0x01A1, 0x0081)], 2));
+        add(&bus, port_device(vec![Port::exact(0xFADF)], 3));
+
+        assert_eq!(read(&bus, 0x001F), Some(1));
+        assert_eq!(read(&bus, 0xFEDF), Some(2));
+        assert_eq!(read(&bus, 0xFADF), Some(3));
+        assert_eq!(read(&bus, 0x003F), None);
     }
 }
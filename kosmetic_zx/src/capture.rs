@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::collections::HashMap;
+use crate::frame::{Frame, Image};
+use crate::ula::SAMPLE_RATE;
+
+/// Frames are written at a flat 50 fps, the real 50.08 Hz is close enough for every player
+pub static CAPTURE_FPS: u32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Uncompressed YUV 4:4:4
+    Y4m,
+    /// Animated GIF, each frame gets its own palette
+    Gif
+}
+
+impl CaptureFormat {
+    /// Picks the format from a `.y4m` or `.gif` extension
+    pub fn from_path(path: &Path) -> Option<CaptureFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "y4m" => Some(CaptureFormat::Y4m),
+            "gif" => Some(CaptureFormat::Gif),
+            _ => None
+        }
+    }
+}
+
+enum VideoWriter {
+    Y4m(BufWriter<File>),
+    Gif(gif::Encoder<BufWriter<File>>)
+}
+
+/// Records every frame it's given, and optionally the beeper to a WAV next to the video
+///
+/// Neither Y4M nor GIF can carry audio, so the WAV is always a separate file
+pub struct Recorder {
+    video: VideoWriter,
+    wav: Option<WavWriter>,
+    width: usize,
+    height: usize,
+    /// `Frame::number` of the last frame recorded
+    last: Option<u64>
+}
+
+impl Recorder {
+    /// `wav` is where to save the audio, if at all
+    pub fn new(path: &Path, format: CaptureFormat, wav: Option<&Path>, width: usize, height: usize) -> std::io::Result<Recorder> {
+        let file = BufWriter::new(File::create(path)?);
+
+        let video = match format {
+            CaptureFormat::Y4m => {
+                let mut file = file;
+                writeln!(file, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, CAPTURE_FPS)?;
+                VideoWriter::Y4m(file)
+            }
+            CaptureFormat::Gif => {
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])
+                    .map_err(Error::other)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(Error::other)?;
+                VideoWriter::Gif(encoder)
+            }
+        };
+
+        Ok(Recorder {
+            video,
+            wav: match wav {
+                Some(wav) => Some(WavWriter::new(wav)?),
+                None => None
+            },
+            width,
+            height,
+            last: None
+        })
+    }
+
+    /// `image` is `source` after any palette and filters, the audio and frame number come from `source`
+    ///
+    /// A skipped frame would throw the video and audio out of time, so it's an error rather than a silent gap
+    pub fn record(&mut self, image: &Image, source: &Frame) -> std::io::Result<()> {
+        if image.width != self.width || image.height != self.height {
+            return Err(Error::new(ErrorKind::InvalidInput, "Frame size changed while recording"));
+        }
+        if let Some(last) = self.last.filter(|last| last + 1 != source.number) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Frames {} to {} are missing from the recording", last + 1, source.number)));
+        }
+        self.last = Some(source.number);
+
+        match &mut self.video {
+            VideoWriter::Y4m(file) => {
+                let pixels = image.rgba.chunks_exact(4);
+                let count = image.width * image.height;
+                let mut planes = [Vec::with_capacity(count), Vec::with_capacity(count), Vec::with_capacity(count)];
+
+                // BT.601, studio range
+                for pixel in pixels {
+                    let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+                    planes[0].push((16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8);
+                    planes[1].push((128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8);
+                    planes[2].push((128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8);
+                }
+
+                file.write_all(b"FRAME\n")?;
+                for plane in &planes {
+                    file.write_all(plane)?;
+                }
+            }
+            VideoWriter::Gif(encoder) => {
+                let mut gif_frame = gif_frame(image);
+                gif_frame.delay = (100 / CAPTURE_FPS) as u16;
+                encoder.write_frame(&gif_frame).map_err(Error::other)?;
+            }
+        }
+
+        if let Some(wav) = &mut self.wav {
+            wav.write(&source.samples)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes everything and fills in the WAV sizes, dropping a `Recorder` without this leaves a broken WAV
+    pub fn finish(self) -> std::io::Result<()> {
+        match self.video {
+            VideoWriter::Y4m(mut file) => file.flush()?,
+            // The trailer is written when the encoder is dropped
+            VideoWriter::Gif(encoder) => drop(encoder)
+        }
+
+        if let Some(wav) = self.wav {
+            wav.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Unfiltered frames only have a handful of colours and get an exact palette, anything busier is quantised
+fn gif_frame(image: &Image) -> gif::Frame<'static> {
+    let mut palette: Vec<u8> = Vec::new();
+    let mut lookup: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(image.width * image.height);
+
+    for pixel in image.rgba.chunks_exact(4) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match lookup.get(&color) {
+            Some(index) => *index,
+            None if lookup.len() < 256 => {
+                let index = lookup.len() as u8;
+                lookup.insert(color, index);
+                palette.extend_from_slice(&color);
+                index
+            }
+            None => {
+                let mut rgba = image.rgba.clone();
+                return gif::Frame::from_rgba_speed(image.width as u16, image.height as u16, &mut rgba, 10);
+            }
+        };
+        indices.push(index);
+    }
+
+    gif::Frame::from_palette_pixels(image.width as u16, image.height as u16, &indices, &palette, None)
+}
+
+/// 16 bit mono PCM
+struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32
+}
+
+impl WavWriter {
+    fn new(path: &Path) -> std::io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        // The RIFF and data lengths are filled in by finish()
+        file.write_all(b"RIFF")?;
+        file.write_all(&0_u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16_u32.to_le_bytes())?;
+        file.write_all(&1_u16.to_le_bytes())?;
+        file.write_all(&1_u16.to_le_bytes())?;
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        file.write_all(&2_u16.to_le_bytes())?;
+        file.write_all(&16_u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0_u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file,
+            data_len: 0
+        })
+    }
+
+    fn write(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
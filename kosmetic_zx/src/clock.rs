@@ -47,14 +47,17 @@ impl Clock {
                 //    self.cpu_clock.send(ClockMessage::Tick).unwrap();
                 //}
 
-                clk.ula_clock.send(ClockMessage::Tick).unwrap();
+                // Nobody left to tick, the ULA has shut down
+                if clk.ula_clock.send(ClockMessage::Tick).is_err() {
+                    break;
+                }
 
                 i += 1;
 
                 let recv = clk.clk_comm.try_recv();
                 if recv.is_ok() {
                     if recv.unwrap() == ClockMessage::Stop {
-                        clk.ula_clock.send(ClockMessage::Stop).unwrap();
+                        let _ = clk.ula_clock.send(ClockMessage::Stop);
                         //clk.cpu_clock.send(ClockMessage::Stop);
                         std::thread::sleep(Duration::from_secs(1));
                         break;
@@ -1,5 +1,7 @@
 use std::fmt::Debug;
+use std::path::Path;
 use derive_more::*;
+#[cfg(feature = "sdl")]
 use sdl2::rect::Point;
 
 pub use u16 as Address;
@@ -19,26 +21,90 @@ pub struct Rect {
     pub h: u16
 }
 
+#[cfg(feature = "sdl")]
 impl Into<Point> for Vec2 {
     fn into(self) -> Point {
         Point::new(self.x as i32, self.y as i32)
     }
 }
 
+#[cfg(feature = "sdl")]
 impl Into<sdl2::rect::Rect> for Rect {
     fn into(self) -> sdl2::rect::Rect {
         sdl2::rect::Rect::new(self.x as i32, self.y as i32, self.w as u32, self.h as u32)
     }
 }
 
+#[cfg(feature = "sdl")]
 impl Into<Point> for Rect {
     fn into(self) -> Point {
         Point::new(self.x as i32, self.y as i32)
     }
 }
 
+#[cfg(feature = "sdl")]
 impl Into<sdl2::rect::Rect> for Vec2 {
     fn into(self) -> sdl2::rect::Rect {
         sdl2::rect::Rect::new(self.x as i32, self.y as i32, self.x as u32, self.y as u32)
     }
-}
\ No newline at end of file
+}
+/// Shared input that counts presses, so several sources can hold the same key or direction
+pub trait Presses<T> {
+    fn press(&self, input: T);
+    /// Takes back one press
+    fn release(&self, input: T);
+}
+
+/// The inputs one source is holding on a shared `Presses`, so it can't let go of anyone else's
+#[derive(Debug, Clone)]
+pub struct Held<T: Copy + Eq>(Vec<T>);
+
+impl<T: Copy + Eq> Default for Held<T> {
+    fn default() -> Self {
+        Held(Vec::new())
+    }
+}
+
+impl<T: Copy + Eq> Held<T> {
+    pub fn new() -> Held<T> {
+        Held::default()
+    }
+
+    pub fn press(&mut self, target: &impl Presses<T>, input: T) {
+        target.press(input);
+        self.0.push(input);
+    }
+
+    /// Does nothing unless this source pressed `input`
+    pub fn release(&mut self, target: &impl Presses<T>, input: T) {
+        if let Some(i) = self.0.iter().position(|held| *held == input) {
+            self.0.swap_remove(i);
+            target.release(input);
+        }
+    }
+
+    pub fn release_all(&mut self, target: &impl Presses<T>) {
+        self.0.drain(..).for_each(|input| target.release(input));
+    }
+}
+
+/// Feeds each line of a map file to `parse_line`, skipping blank lines and `#` comments
+///
+/// A line that doesn't parse fails the whole file, with the path and line number in front of the error
+pub fn read_lines(path: &Path, mut parse_line: impl FnMut(&str) -> Result<(), String>) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        parse_line(line).map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}:{}: {}", path.display(), number + 1, e)
+        ))?;
+    }
+
+    Ok(())
+}
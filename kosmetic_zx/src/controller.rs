@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::path::Path;
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use crate::common::{read_lines, Held};
+use crate::joystick::{JoyInput, JoystickState};
+use crate::keyboard::{KeyboardMatrix, SpecKey};
+
+/// What a controller button does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PadAction {
+    Joystick(JoyInput),
+    /// Held for as long as the button is, for games that want more than one fire button
+    Keys(Vec<SpecKey>)
+}
+
+/// Controller buttons to joystick inputs and Spectrum keys, the left stick is always the joystick
+#[derive(Debug, Clone)]
+pub struct ControllerMap {
+    buttons: HashMap<Button, PadAction>
+}
+
+impl Default for ControllerMap {
+    /// The D-pad and face buttons are the joystick, Start is ENTER and Back is SPACE
+    fn default() -> Self {
+        let buttons = [
+            (Button::DPadUp, PadAction::Joystick(JoyInput::Up)),
+            (Button::DPadDown, PadAction::Joystick(JoyInput::Down)),
+            (Button::DPadLeft, PadAction::Joystick(JoyInput::Left)),
+            (Button::DPadRight, PadAction::Joystick(JoyInput::Right)),
+            (Button::A, PadAction::Joystick(JoyInput::Fire)),
+            (Button::B, PadAction::Joystick(JoyInput::Fire)),
+            (Button::X, PadAction::Joystick(JoyInput::Fire)),
+            (Button::Y, PadAction::Joystick(JoyInput::Fire)),
+            (Button::Start, PadAction::Keys(vec![SpecKey::Enter])),
+            (Button::Back, PadAction::Keys(vec![SpecKey::Space]))
+        ];
+
+        ControllerMap {
+            buttons: buttons.into_iter().collect()
+        }
+    }
+}
+
+impl ControllerMap {
+    /// The default map with the entries from a file on top
+    ///
+    /// One button per line, `button = ACTION`, with buttons named as SDL names them (`a`, `start`, `leftshoulder`,
+    /// `dpup`...). Actions are `UP`, `DOWN`, `LEFT`, `RIGHT`, `FIRE`, `NONE` or Spectrum keys joined with `+`. `#`
+    /// starts a comment
+    pub fn load(path: &Path) -> std::io::Result<ControllerMap> {
+        let mut map = ControllerMap::default();
+        read_lines(path, |line| map.parse_line(line))?;
+        Ok(map)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let (button, action) = line.split_once('=').ok_or_else(|| format!("Expected button = ACTION in \"{}\"", line))?;
+        let button = Button::from_string(button.trim()).ok_or_else(|| format!("Unknown controller button \"{}\"", button.trim()))?;
+
+        let action = match action.trim().to_ascii_uppercase().as_str() {
+            "UP" => PadAction::Joystick(JoyInput::Up),
+            "DOWN" => PadAction::Joystick(JoyInput::Down),
+            "LEFT" => PadAction::Joystick(JoyInput::Left),
+            "RIGHT" => PadAction::Joystick(JoyInput::Right),
+            "FIRE" => PadAction::Joystick(JoyInput::Fire),
+            "NONE" => {
+                self.buttons.remove(&button);
+                return Ok(());
+            }
+            keys => PadAction::Keys(keys.split('+').map(|k| k.trim().parse()).collect::<Result<Vec<SpecKey>, _>>()?)
+        };
+
+        self.buttons.insert(button, action);
+        Ok(())
+    }
+
+    pub fn get(&self, button: Button) -> Option<&PadAction> {
+        self.buttons.get(&button)
+    }
+}
+
+/// One open controller and whatever it's holding down, so unplugging it only lets go of its own inputs
+struct Pad {
+    controller: GameController,
+    inputs: Held<JoyInput>,
+    keys: Held<SpecKey>
+}
+
+impl Pad {
+    fn press(&mut self, action: &PadAction, joystick: &JoystickState, keyboard: &KeyboardMatrix) {
+        match action {
+            PadAction::Joystick(input) => self.inputs.press(joystick, *input),
+            PadAction::Keys(keys) => keys.iter().for_each(|k| self.keys.press(keyboard, *k))
+        }
+    }
+
+    /// A button held since before the pad was opened does nothing
+    fn release(&mut self, action: &PadAction, joystick: &JoystickState, keyboard: &KeyboardMatrix) {
+        match action {
+            PadAction::Joystick(input) => self.inputs.release(joystick, *input),
+            PadAction::Keys(keys) => keys.iter().for_each(|k| self.keys.release(keyboard, *k))
+        }
+    }
+
+    fn release_all(&mut self, joystick: &JoystickState, keyboard: &KeyboardMatrix) {
+        self.inputs.release_all(joystick);
+        self.keys.release_all(keyboard);
+    }
+}
+
+/// What `Controllers::handle_event` made of an event, the ones worth telling the user about carry the controller name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControllerEvent {
+    /// Not a controller event, it's left for the caller
+    Ignored,
+    Handled,
+    Connected(String),
+    Disconnected(String),
+    /// A controller was plugged in but SDL couldn't open it, by device index
+    OpenFailed(u32, String)
+}
+
+/// Every connected controller, opened and closed as they're plugged in and out
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    map: ControllerMap,
+    /// By instance id, which is what every event after the first one refers to them by
+    open: HashMap<u32, Pad>
+}
+
+impl Controllers {
+    /// SDL sends an added event for each controller that's already connected, so they're all picked up from there
+    pub fn new(subsystem: GameControllerSubsystem, map: ControllerMap) -> Controllers {
+        Controllers {
+            subsystem,
+            map,
+            open: HashMap::new()
+        }
+    }
+
+    /// Handles controller events, anything else is left alone and `ControllerEvent::Ignored` is returned
+    pub fn handle_event(&mut self, event: &Event, joystick: &JoystickState, keyboard: &KeyboardMatrix) -> ControllerEvent {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                return match self.subsystem.open(*which) {
+                    Ok(controller) => {
+                        let name = controller.name();
+                        self.open.insert(controller.instance_id(), Pad { controller, inputs: Held::new(), keys: Held::new() });
+                        ControllerEvent::Connected(name)
+                    }
+                    Err(e) => ControllerEvent::OpenFailed(*which, e.to_string())
+                };
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(mut pad) = self.open.remove(which) {
+                    // Whatever it was holding would otherwise stay held forever
+                    pad.release_all(joystick, keyboard);
+                    return ControllerEvent::Disconnected(pad.controller.name());
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let (Some(pad), Some(action)) = (self.open.get_mut(which), self.map.get(*button)) {
+                    pad.press(action, joystick, keyboard);
+                }
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let (Some(pad), Some(action)) = (self.open.get_mut(which), self.map.get(*button)) {
+                    pad.release(action, joystick, keyboard);
+                }
+            }
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                let directions = match axis {
+                    Axis::LeftX => Some((JoyInput::Left, JoyInput::Right)),
+                    Axis::LeftY => Some((JoyInput::Up, JoyInput::Down)),
+                    _ => None
+                };
+                if let (Some(pad), Some((negative, positive))) = (self.open.get_mut(which), directions) {
+                    pad.inputs.set_axis(joystick, negative, positive, *value);
+                }
+            }
+            Event::ControllerDeviceRemapped { .. } => {}
+            _ => return ControllerEvent::Ignored
+        }
+
+        ControllerEvent::Handled
+    }
+}
@@ -1,7 +1,72 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::common::Byte;
+
 pub struct Cpu {
 
 }
 
 impl Cpu {
 
-}
\ No newline at end of file
+}
+
+/// The CPU's /INT pin, any device can hold it and the CPU samples it at the end of each instruction
+#[derive(Debug, Clone, Default)]
+pub struct InterruptLine {
+    asserted: Arc<AtomicBool>
+}
+
+impl InterruptLine {
+    pub fn new() -> InterruptLine {
+        InterruptLine::default()
+    }
+
+    pub fn assert(&self) {
+        self.asserted.store(true, Ordering::Release);
+    }
+
+    pub fn release(&self) {
+        self.asserted.store(false, Ordering::Release);
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.asserted.load(Ordering::Acquire)
+    }
+}
+
+/// The IR pair the CPU puts on the address bus during the refresh half of each M1 cycle, and the T-state it did
+///
+/// T-states count from the start of the frame like the ULA's, so a display fetch is only corrupted by a refresh
+/// on the same T-state. Packed as `0x1_TTTTT_IIRR` with the top bit marking a refresh, so every part is always
+/// seen together.
+///
+/// Nothing drives this yet, `Cpu` is still a stub, so there's no snow until it calls `refresh` on every M1
+#[derive(Debug, Clone, Default)]
+pub struct RefreshLine {
+    ir: Arc<AtomicU64>
+}
+
+impl RefreshLine {
+    pub fn new() -> RefreshLine {
+        RefreshLine::default()
+    }
+
+    /// The CPU refreshed address IR on frame T-state `t_state`
+    pub fn refresh(&self, t_state: u32, i: Byte, r: Byte) {
+        self.ir.store(1 << 48 | (t_state as u64) << 16 | (i as u64) << 8 | r as u64, Ordering::Release);
+    }
+
+    pub fn clear(&self) {
+        self.ir.store(0, Ordering::Release);
+    }
+
+    /// (I, R) if the CPU refreshed on exactly `t_state`
+    pub fn at(&self, t_state: u32) -> Option<(Byte, Byte)> {
+        let ir = self.ir.load(Ordering::Acquire);
+        if ir >> 48 != 0 && (ir >> 16) as u32 == t_state {
+            Some(((ir >> 8) as Byte, ir as Byte))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::str::FromStr;
+use crate::frame::Image;
+
+/// A post-processing step run on the CPU over a converted frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Darkens every other line, looks best after `Scale2x` or `Scale3x`
+    Scanlines,
+    /// AdvMAME2x / EPX edge-preserving 2x upscale
+    Scale2x,
+    /// AdvMAME3x edge-preserving 3x upscale
+    Scale3x,
+    /// Smears colour but not brightness horizontally, roughly what PAL encoding does to the picture
+    PalBlur
+}
+
+impl Filter {
+    pub fn apply(&self, image: &Image) -> Image {
+        match self {
+            Filter::Scanlines => scanlines(image),
+            Filter::Scale2x => scale2x(image),
+            Filter::Scale3x => scale3x(image),
+            Filter::PalBlur => pal_blur(image)
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanlines" => Ok(Filter::Scanlines),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            "pal" => Ok(Filter::PalBlur),
+            _ => Err(format!("Unknown filter \"{}\"", s))
+        }
+    }
+}
+
+/// Runs each filter in turn
+pub fn apply_all(filters: &[Filter], image: Image) -> Image {
+    filters.iter().fold(image, |image, filter| filter.apply(&image))
+}
+
+fn scanlines(image: &Image) -> Image {
+    let mut out = image.clone();
+    let row = image.width * 4;
+
+    for line in out.rgba.chunks_exact_mut(row).skip(1).step_by(2) {
+        for pixel in line.chunks_exact_mut(4) {
+            for channel in &mut pixel[..3] {
+                *channel = (*channel as u16 * 5 / 8) as u8;
+            }
+        }
+    }
+
+    out
+}
+
+/// The source pixel at (x, y) with its neighbours clamped to the image edge
+fn neighbourhood(image: &Image, x: usize, y: usize) -> [[u8; 4]; 9] {
+    let left = x.saturating_sub(1);
+    let right = (x + 1).min(image.width - 1);
+    let up = y.saturating_sub(1);
+    let down = (y + 1).min(image.height - 1);
+
+    [
+        image.get(left, up), image.get(x, up), image.get(right, up),
+        image.get(left, y), image.get(x, y), image.get(right, y),
+        image.get(left, down), image.get(x, down), image.get(right, down)
+    ]
+}
+
+fn scale2x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 2, image.height * 2);
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let [_, b, _, d, e, f, _, h, _] = neighbourhood(image, x, y);
+
+            let (e0, e1, e2, e3) = if b != h && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e }
+                )
+            } else {
+                (e, e, e, e)
+            };
+
+            out.set(x * 2, y * 2, e0);
+            out.set(x * 2 + 1, y * 2, e1);
+            out.set(x * 2, y * 2 + 1, e2);
+            out.set(x * 2 + 1, y * 2 + 1, e3);
+        }
+    }
+
+    out
+}
+
+fn scale3x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 3, image.height * 3);
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let [a, b, c, d, e, f, g, h, i] = neighbourhood(image, x, y);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e }
+                ]
+            } else {
+                [e; 9]
+            };
+
+            for (n, pixel) in block.iter().enumerate() {
+                out.set(x * 3 + n % 3, y * 3 + n / 3, *pixel);
+            }
+        }
+    }
+
+    out
+}
+
+fn pal_blur(image: &Image) -> Image {
+    let mut out = image.clone();
+
+    for y in 0..image.height {
+        let yuv: Vec<[f32; 3]> = (0..image.width).map(|x| {
+            let [r, g, b, _] = image.get(x, y);
+            let (r, g, b) = (r as f32, g as f32, b as f32);
+            let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+            [luma, 0.492 * (b - luma), 0.877 * (r - luma)]
+        }).collect();
+
+        for x in 0..image.width {
+            // Brightness stays sharp, colour difference is averaged over 5 pixels
+            let mut u = 0.0;
+            let mut v = 0.0;
+            let mut weight = 0.0;
+            for (offset, w) in [(-2, 1.0), (-1, 2.0), (0, 3.0), (1, 2.0), (2, 1.0)] {
+                let sx = (x as isize + offset).clamp(0, image.width as isize - 1) as usize;
+                u += yuv[sx][1] * w;
+                v += yuv[sx][2] * w;
+                weight += w;
+            }
+            let (luma, u, v) = (yuv[x][0], u / weight, v / weight);
+
+            let r = luma + v / 0.877;
+            let b = luma + u / 0.492;
+            let g = (luma - 0.299 * r - 0.114 * b) / 0.587;
+
+            let alpha = image.get(x, y)[3];
+            out.set(x, y, [
+                r.round().clamp(0.0, 255.0) as u8,
+                g.round().clamp(0.0, 255.0) as u8,
+                b.round().clamp(0.0, 255.0) as u8,
+                alpha
+            ]);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grey image with each pixel's level given
+    fn image(width: usize, levels: &[u8]) -> Image {
+        let mut image = Image::new(width, levels.len() / width);
+        for (i, level) in levels.iter().enumerate() {
+            image.set(i % width, i / width, [*level, *level, *level, 0xFF]);
+        }
+        image
+    }
+
+    fn levels(image: &Image) -> Vec<u8> {
+        image.rgba.chunks_exact(4).map(|pixel| pixel[0]).collect()
+    }
+
+    fn luma([r, g, b, _]: [u8; 4]) -> f32 {
+        0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+    }
+
+    /// A diagonal edge through the middle pixel
+    static DIAGONAL: [u8; 9] = [
+        1, 1, 0,
+        1, 0, 0,
+        0, 0, 0
+    ];
+
+    #[test]
+    fn parses_filter_names() {
+        assert_eq!("scale2x".parse(), Ok(Filter::Scale2x));
+        assert_eq!("pal".parse(), Ok(Filter::PalBlur));
+        assert!("scale4x".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn scale2x_doubles_flat_images_unchanged() {
+        let out = Filter::Scale2x.apply(&image(3, &[7; 6]));
+        assert_eq!((out.width, out.height), (6, 4));
+        assert_eq!(levels(&out), vec![7; 24]);
+    }
+
+    #[test]
+    fn scale2x_rounds_off_diagonals() {
+        let out = Filter::Scale2x.apply(&image(3, &DIAGONAL));
+        // Only the corner of the middle pixel that faces the edge takes the edge colour
+        assert_eq!(out.get(2, 2)[0], 1);
+        assert_eq!(out.get(3, 2)[0], 0);
+        assert_eq!(out.get(2, 3)[0], 0);
+        assert_eq!(out.get(3, 3)[0], 0);
+    }
+
+    #[test]
+    fn scale2x_edge_cases() {
+        // A single pixel only has itself for neighbours
+        assert_eq!(levels(&Filter::Scale2x.apply(&image(1, &[9]))), vec![9; 4]);
+
+        // Clamping at the image edge mustn't invent colours in the corner pixels
+        let out = Filter::Scale2x.apply(&image(3, &DIAGONAL));
+        assert_eq!([out.get(0, 0)[0], out.get(1, 0)[0], out.get(0, 1)[0], out.get(1, 1)[0]], [1; 4]);
+
+        // A checkerboard has no edge to follow, EPX leaves it as blocks
+        let checkerboard = [1, 0, 1, 0, 1, 0, 1, 0, 1];
+        let out = Filter::Scale2x.apply(&image(3, &checkerboard));
+        assert_eq!([out.get(2, 2)[0], out.get(3, 2)[0], out.get(2, 3)[0], out.get(3, 3)[0]], [1; 4]);
+
+        // Lines one pixel wide stay unbroken
+        let out = Filter::Scale2x.apply(&image(3, &[0, 1, 0, 0, 1, 0, 0, 1, 0]));
+        assert!((0..6).all(|y| out.get(2, y)[0] == 1 && out.get(3, y)[0] == 1));
+    }
+
+    #[test]
+    fn scale3x_triples_and_rounds_off_diagonals() {
+        let out = Filter::Scale3x.apply(&image(3, &DIAGONAL));
+        assert_eq!((out.width, out.height), (9, 9));
+
+        let middle: Vec<u8> = (3..6).flat_map(|y| (3..6).map(move |x| (x, y))).map(|(x, y)| out.get(x, y)[0]).collect();
+        assert_eq!(middle, vec![1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(levels(&Filter::Scale3x.apply(&image(2, &[5; 4]))), vec![5; 36]);
+    }
+
+    #[test]
+    fn scanlines_darken_every_other_line() {
+        let out = Filter::Scanlines.apply(&image(2, &[200; 6]));
+        assert_eq!(levels(&out), vec![200, 200, 125, 125, 200, 200]);
+        assert!(out.rgba.chunks_exact(4).all(|pixel| pixel[3] == 0xFF));
+    }
+
+    #[test]
+    fn pal_blur_smears_colour_but_not_brightness() {
+        let grey = image(4, &[10, 200, 30, 90]);
+        assert_eq!(levels(&Filter::PalBlur.apply(&grey)), levels(&grey));
+
+        // Muted enough that nothing clips, which would change the brightness
+        let mut stripes = Image::new(6, 1);
+        for x in 0..6 {
+            stripes.set(x, 0, if x < 3 { [0xC0, 0x60, 0x60, 0xFF] } else { [0x60, 0x60, 0xC0, 0xFF] });
+        }
+        let out = Filter::PalBlur.apply(&stripes);
+
+        for x in 0..6 {
+            assert!((luma(out.get(x, 0)) - luma(stripes.get(x, 0))).abs() < 2.0, "brightness changed at {}", x);
+        }
+        // Next to the edge each side picks up some of the other's colour, further away it doesn't
+        assert!(out.get(2, 0)[2] > 0x60);
+        assert!(out.get(3, 0)[0] > 0x60);
+        assert_eq!(out.get(0, 0), stripes.get(0, 0));
+    }
+}
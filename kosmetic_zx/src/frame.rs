@@ -0,0 +1,98 @@
+use crate::common::Byte;
+use crate::palette::Palette;
+
+/// Usually a Spectrum colour index: bits 0-2 are the GRB colour and bit 3 is BRIGHT
+///
+/// With `ULAPLUS_PIXEL` set the low byte is a GRB332 ULAplus colour instead
+pub type Pixel = u16;
+pub static ULAPLUS_PIXEL: Pixel = 0x100;
+
+/// One complete ULA frame
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Pixel>,
+    /// Counts up from 0 at power on
+    pub number: u64,
+    /// Mono beeper output for the duration of the frame, at `ula::SAMPLE_RATE`
+    pub samples: Vec<i16>
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize, number: u64) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            number,
+            samples: Vec::new()
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Pixel) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Pixel {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Overwrites row `y` with `line`, which must be exactly `width` pixels
+    pub fn set_line(&mut self, y: usize, line: &[Pixel]) {
+        self.pixels[y * self.width..(y + 1) * self.width].copy_from_slice(line);
+    }
+
+    /// Converts the frame to colours through `palette`
+    pub fn to_image(&self, palette: &Palette) -> Image {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in &self.pixels {
+            let [r, g, b] = palette.rgb(*pixel);
+            rgba.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+
+        Image {
+            width: self.width,
+            height: self.height,
+            rgba
+        }
+    }
+}
+
+/// A frame after palette conversion, what gets filtered, shown, captured and saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Packed RGBA8888, row by row with no padding
+    pub rgba: Vec<u8>
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            rgba: vec![0xFF; width * height * 4]
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [self.rgba[i], self.rgba[i + 1], self.rgba[i + 2], self.rgba[i + 3]]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        let i = (y * self.width + x) * 4;
+        self.rgba[i..i + 4].copy_from_slice(&pixel);
+    }
+}
+
+/// Builds a colour index from the 3 GRB bits of `data` and the BRIGHT flag
+pub fn color_index(data: Byte, bright: bool) -> Pixel {
+    ((data & 0b00000111) | ((bright as Byte) << 3)) as Pixel
+}
+
+pub fn ulaplus_pixel(grb: Byte) -> Pixel {
+    ULAPLUS_PIXEL | grb as Pixel
+}
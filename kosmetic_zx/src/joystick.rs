@@ -0,0 +1,137 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use crate::common::{Byte, Held, Presses};
+use crate::keyboard::SpecKey;
+
+/// Stick directions and fire, the discriminants are the Kempston bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JoyInput {
+    Right = 0b00001,
+    Left = 0b00010,
+    Down = 0b00100,
+    Up = 0b01000,
+    Fire = 0b10000
+}
+
+/// What the host joystick is plugged into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JoystickInterface {
+    #[default]
+    None,
+    /// Its own port at 0x1F
+    Kempston,
+    /// Interface 2 port 1, keys 6-0
+    Sinclair1,
+    /// Interface 2 port 2, keys 1-5
+    Sinclair2,
+    /// Cursor, Protek and AGF, the cursor keys and 0
+    Cursor
+}
+
+static SINCLAIR_1: [(JoyInput, SpecKey); 5] = [
+    (JoyInput::Left, SpecKey::Num6), (JoyInput::Right, SpecKey::Num7), (JoyInput::Down, SpecKey::Num8),
+    (JoyInput::Up, SpecKey::Num9), (JoyInput::Fire, SpecKey::Num0)
+];
+static SINCLAIR_2: [(JoyInput, SpecKey); 5] = [
+    (JoyInput::Left, SpecKey::Num1), (JoyInput::Right, SpecKey::Num2), (JoyInput::Down, SpecKey::Num3),
+    (JoyInput::Up, SpecKey::Num4), (JoyInput::Fire, SpecKey::Num5)
+];
+static CURSOR: [(JoyInput, SpecKey); 5] = [
+    (JoyInput::Left, SpecKey::Num5), (JoyInput::Down, SpecKey::Num6), (JoyInput::Up, SpecKey::Num7),
+    (JoyInput::Right, SpecKey::Num8), (JoyInput::Fire, SpecKey::Num0)
+];
+
+impl JoystickInterface {
+    /// The keys each input presses, for the interfaces that just short keyboard lines
+    pub fn keys(&self) -> Option<&'static [(JoyInput, SpecKey)]> {
+        match self {
+            JoystickInterface::Sinclair1 => Some(&SINCLAIR_1),
+            JoystickInterface::Sinclair2 => Some(&SINCLAIR_2),
+            JoystickInterface::Cursor => Some(&CURSOR),
+            _ => None
+        }
+    }
+}
+
+impl FromStr for JoystickInterface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(JoystickInterface::None),
+            "kempston" => Ok(JoystickInterface::Kempston),
+            "sinclair1" => Ok(JoystickInterface::Sinclair1),
+            "sinclair2" => Ok(JoystickInterface::Sinclair2),
+            "cursor" => Ok(JoystickInterface::Cursor),
+            _ => Err(format!("Unknown joystick interface \"{}\"", s))
+        }
+    }
+}
+
+/// How far an analogue stick has to move before it counts as a direction
+pub static AXIS_DEADZONE: i16 = 8000;
+
+/// What the host joystick is doing, shared between the frontend and whichever interface reads it
+///
+/// Like `KeyboardMatrix` each input counts its presses, so host keys and controllers can hold the same direction.
+/// A `Held` keeps track of what one source pressed
+#[derive(Debug, Clone, Default)]
+pub struct JoystickState {
+    /// How many times each input is pressed, by Kempston bit
+    presses: Arc<[AtomicU8; 5]>
+}
+
+impl JoystickState {
+    pub fn new() -> JoystickState {
+        JoystickState::default()
+    }
+
+    fn presses(&self, input: JoyInput) -> &AtomicU8 {
+        &self.presses[(input as Byte).trailing_zeros() as usize]
+    }
+
+    pub fn press(&self, input: JoyInput) {
+        let _ = self.presses(input).fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_add(1));
+    }
+
+    /// Takes back one press, the input only lets go once nothing is pressing it
+    pub fn release(&self, input: JoyInput) {
+        let _ = self.presses(input).fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+    }
+
+    pub fn is_pressed(&self, input: JoyInput) -> bool {
+        self.presses(input).load(Ordering::Acquire) != 0
+    }
+
+    /// In Kempston order, bit 0 right through to bit 4 fire
+    pub fn bits(&self) -> Byte {
+        self.presses.iter().enumerate()
+            .filter(|(_, presses)| presses.load(Ordering::Acquire) != 0)
+            .fold(0, |bits, (bit, _)| bits | 1 << bit)
+    }
+}
+
+impl Presses<JoyInput> for JoystickState {
+    fn press(&self, input: JoyInput) {
+        JoystickState::press(self, input)
+    }
+
+    fn release(&self, input: JoyInput) {
+        JoystickState::release(self, input)
+    }
+}
+
+impl Held<JoyInput> {
+    /// Turns one analogue axis into a pair of directions
+    pub fn set_axis(&mut self, joystick: &JoystickState, negative: JoyInput, positive: JoyInput, value: i16) {
+        self.release(joystick, negative);
+        self.release(joystick, positive);
+
+        if value < -AXIS_DEADZONE {
+            self.press(joystick, negative);
+        } else if value > AXIS_DEADZONE {
+            self.press(joystick, positive);
+        }
+    }
+}
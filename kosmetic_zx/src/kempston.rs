@@ -0,0 +1,43 @@
+use std::thread;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::bus::{BusMessage, Port};
+use crate::joystick::JoystickState;
+
+/// A Kempston joystick interface, normally read at 0x1F but only A5 is decoded
+pub struct Kempston {
+    state: JoystickState,
+    receiver: Receiver<BusMessage>
+}
+
+impl Kempston {
+    pub fn new(state: JoystickState) -> Sender<BusMessage> {
+        let (tx, rx) = bounded(128);
+
+        thread::spawn(move || {
+            let mut kempston = Kempston {
+                state,
+                receiver: rx
+            };
+
+            kempston.message_loop();
+        });
+
+        tx
+    }
+
+    fn message_loop(&mut self) {
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
+                BusMessage::MemPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::MemGet(_, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                // Active high, with the unused top 3 bits reading 0
+                BusMessage::IOGet(_, s) => { let _ = s.send(BusMessage::IOReadOk(self.state.bits())); },
+                BusMessage::GetRanges(s) => {
+                    let _ = s.send(BusMessage::RangesRet(vec![], vec![], vec![Port::new(0x0020, 0x0000)], vec![]));
+                },
+                _ => {}
+            }
+        }
+    }
+}
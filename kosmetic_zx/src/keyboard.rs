@@ -0,0 +1,263 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use crate::common::{Byte, Presses};
+use crate::joystick::{JoyInput, JoystickState};
+
+/// The 40 keys of the 48K, in half-row order starting from port 0xFEFE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpecKey {
+    CapsShift, Z, X, C, V,
+    A, S, D, F, G,
+    Q, W, E, R, T,
+    Num1, Num2, Num3, Num4, Num5,
+    Num0, Num9, Num8, Num7, Num6,
+    P, O, I, U, Y,
+    Enter, L, K, J, H,
+    Space, SymbolShift, M, N, B
+}
+
+static KEYS: [SpecKey; 40] = [
+    SpecKey::CapsShift, SpecKey::Z, SpecKey::X, SpecKey::C, SpecKey::V,
+    SpecKey::A, SpecKey::S, SpecKey::D, SpecKey::F, SpecKey::G,
+    SpecKey::Q, SpecKey::W, SpecKey::E, SpecKey::R, SpecKey::T,
+    SpecKey::Num1, SpecKey::Num2, SpecKey::Num3, SpecKey::Num4, SpecKey::Num5,
+    SpecKey::Num0, SpecKey::Num9, SpecKey::Num8, SpecKey::Num7, SpecKey::Num6,
+    SpecKey::P, SpecKey::O, SpecKey::I, SpecKey::U, SpecKey::Y,
+    SpecKey::Enter, SpecKey::L, SpecKey::K, SpecKey::J, SpecKey::H,
+    SpecKey::Space, SpecKey::SymbolShift, SpecKey::M, SpecKey::N, SpecKey::B
+];
+
+static NAMES: [&str; 40] = [
+    "CAPS", "Z", "X", "C", "V",
+    "A", "S", "D", "F", "G",
+    "Q", "W", "E", "R", "T",
+    "1", "2", "3", "4", "5",
+    "0", "9", "8", "7", "6",
+    "P", "O", "I", "U", "Y",
+    "ENTER", "L", "K", "J", "H",
+    "SPACE", "SYM", "M", "N", "B"
+];
+
+impl SpecKey {
+    pub fn all() -> &'static [SpecKey] {
+        &KEYS
+    }
+
+    /// Letters and digits are themselves, the rest are CAPS, SYM, ENTER and SPACE
+    pub fn name(&self) -> &'static str {
+        NAMES[*self as usize]
+    }
+
+    /// The half-row (0-7, selected by A8-A15) and the bit the key pulls low in it
+    pub fn position(&self) -> (usize, Byte) {
+        let index = *self as usize;
+        (index / 5, 1 << (index % 5))
+    }
+}
+
+impl FromStr for SpecKey {
+    type Err = String;
+
+    /// Case insensitive, see `SpecKey::name`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NAMES.iter().position(|name| name.eq_ignore_ascii_case(s))
+            .map(|index| KEYS[index])
+            .ok_or_else(|| format!("Unknown Spectrum key \"{}\"", s))
+    }
+}
+
+/// Which keys are held down, shared between whoever presses them and the ULA that reads them
+///
+/// Each key counts its presses, so a key held by two sources (say the keyboard and a controller) stays down until
+/// both let go. Every source has to release exactly what it pressed, a `Held` keeps track of that
+#[derive(Debug, Clone, Default)]
+pub struct KeyboardMatrix {
+    /// How many times each key is pressed, by half-row and then bit
+    presses: Arc<[[AtomicU8; 5]; 8]>,
+    /// A joystick wired into the keyboard lines, and the key each of its inputs presses
+    joystick: Option<(JoystickState, &'static [(JoyInput, SpecKey)])>
+}
+
+impl KeyboardMatrix {
+    pub fn new() -> KeyboardMatrix {
+        KeyboardMatrix::default()
+    }
+
+    /// A keyboard with a Sinclair or Cursor style joystick on it, see `JoystickInterface::keys`
+    pub fn with_joystick(joystick: JoystickState, keys: &'static [(JoyInput, SpecKey)]) -> KeyboardMatrix {
+        KeyboardMatrix {
+            joystick: Some((joystick, keys)),
+            ..KeyboardMatrix::default()
+        }
+    }
+
+    fn presses(&self, key: SpecKey) -> &AtomicU8 {
+        let index = key as usize;
+        &self.presses[index / 5][index % 5]
+    }
+
+    pub fn press(&self, key: SpecKey) {
+        let _ = self.presses(key).fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_add(1));
+    }
+
+    /// Takes back one press, the key only comes up once nothing is pressing it
+    pub fn release(&self, key: SpecKey) {
+        let _ = self.presses(key).fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+    }
+
+    pub fn is_pressed(&self, key: SpecKey) -> bool {
+        self.presses(key).load(Ordering::Acquire) != 0
+    }
+
+    /// The 5 key bits for a read of port 0xFE, active low
+    ///
+    /// Every half-row with its line low in `high_byte` is selected, and their keys are combined
+    pub fn read(&self, high_byte: Byte) -> Byte {
+        let mut pressed = self.presses.iter().enumerate()
+            .filter(|(row, _)| high_byte & (1 << row) == 0)
+            .flat_map(|(_, keys)| keys.iter().enumerate())
+            .filter(|(_, presses)| presses.load(Ordering::Acquire) != 0)
+            .fold(0, |pressed, (bit, _)| pressed | 1 << bit);
+
+        if let Some((joystick, keys)) = &self.joystick {
+            for (input, key) in keys.iter() {
+                let (row, bit) = key.position();
+                if high_byte & (1 << row) == 0 && joystick.is_pressed(*input) {
+                    pressed |= bit;
+                }
+            }
+        }
+
+        !pressed & 0b00011111
+    }
+}
+
+impl Presses<SpecKey> for KeyboardMatrix {
+    fn press(&self, key: SpecKey) {
+        KeyboardMatrix::press(self, key)
+    }
+
+    fn release(&self, key: SpecKey) {
+        KeyboardMatrix::release(self, key)
+    }
+}
+
+/// The host key that stands in for each Spectrum key, by position on a US layout
+///
+/// Either Shift is CAPS SHIFT, and either Ctrl or Alt is SYMBOL SHIFT
+#[cfg(feature = "sdl")]
+pub fn from_scancode(scancode: sdl2::keyboard::Scancode) -> Option<SpecKey> {
+    use sdl2::keyboard::Scancode;
+
+    Some(match scancode {
+        Scancode::LShift | Scancode::RShift => SpecKey::CapsShift,
+        Scancode::LCtrl | Scancode::RCtrl | Scancode::LAlt | Scancode::RAlt => SpecKey::SymbolShift,
+        Scancode::Return | Scancode::KpEnter => SpecKey::Enter,
+        Scancode::Space => SpecKey::Space,
+        Scancode::Num1 => SpecKey::Num1,
+        Scancode::Num2 => SpecKey::Num2,
+        Scancode::Num3 => SpecKey::Num3,
+        Scancode::Num4 => SpecKey::Num4,
+        Scancode::Num5 => SpecKey::Num5,
+        Scancode::Num6 => SpecKey::Num6,
+        Scancode::Num7 => SpecKey::Num7,
+        Scancode::Num8 => SpecKey::Num8,
+        Scancode::Num9 => SpecKey::Num9,
+        Scancode::Num0 => SpecKey::Num0,
+        Scancode::A => SpecKey::A,
+        Scancode::B => SpecKey::B,
+        Scancode::C => SpecKey::C,
+        Scancode::D => SpecKey::D,
+        Scancode::E => SpecKey::E,
+        Scancode::F => SpecKey::F,
+        Scancode::G => SpecKey::G,
+        Scancode::H => SpecKey::H,
+        Scancode::I => SpecKey::I,
+        Scancode::J => SpecKey::J,
+        Scancode::K => SpecKey::K,
+        Scancode::L => SpecKey::L,
+        Scancode::M => SpecKey::M,
+        Scancode::N => SpecKey::N,
+        Scancode::O => SpecKey::O,
+        Scancode::P => SpecKey::P,
+        Scancode::Q => SpecKey::Q,
+        Scancode::R => SpecKey::R,
+        Scancode::S => SpecKey::S,
+        Scancode::T => SpecKey::T,
+        Scancode::U => SpecKey::U,
+        Scancode::V => SpecKey::V,
+        Scancode::W => SpecKey::W,
+        Scancode::X => SpecKey::X,
+        Scancode::Y => SpecKey::Y,
+        Scancode::Z => SpecKey::Z,
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Held;
+    use crate::joystick::JoystickInterface;
+
+    #[test]
+    fn a_key_stays_down_until_every_source_lets_go() {
+        let matrix = KeyboardMatrix::new();
+        let mut keyboard = Held::new();
+        let mut controller = Held::new();
+
+        keyboard.press(&matrix, SpecKey::Enter);
+        controller.press(&matrix, SpecKey::Enter);
+        keyboard.release_all(&matrix);
+        assert!(matrix.is_pressed(SpecKey::Enter));
+
+        // Letting go of a key it never pressed mustn't take the controller's press away
+        keyboard.release(&matrix, SpecKey::Enter);
+        assert!(matrix.is_pressed(SpecKey::Enter));
+
+        controller.release(&matrix, SpecKey::Enter);
+        assert!(!matrix.is_pressed(SpecKey::Enter));
+        assert_eq!(matrix.read(0xBF), 0b00011111);
+    }
+
+    #[test]
+    fn reads_every_selected_half_row() {
+        let matrix = KeyboardMatrix::new();
+        matrix.press(SpecKey::CapsShift);
+        matrix.press(SpecKey::A);
+        matrix.press(SpecKey::P);
+        matrix.press(SpecKey::B);
+
+        let cases = [
+            // (high byte, active low keys)
+            (0xFF, 0b00011111),
+            // A8 alone is CAPS SHIFT to V
+            (0xFE, 0b00011110),
+            // A8 and A9 together, CAPS SHIFT and A share bit 0
+            (0xFC, 0b00011110),
+            // A13 is P, A15 is B
+            (0xDF, 0b00011110),
+            (0x7F, 0b00001111),
+            (0x5F, 0b00001110),
+            // Every line low, as the ROM does to check for any key at all
+            (0x00, 0b00001110),
+        ];
+
+        for (high_byte, keys) in cases {
+            assert_eq!(matrix.read(high_byte), keys, "high byte {:02X}", high_byte);
+        }
+    }
+
+    #[test]
+    fn joystick_keys_merge_with_pressed_keys() {
+        let joystick = JoystickState::new();
+        let matrix = KeyboardMatrix::with_joystick(joystick.clone(), JoystickInterface::Sinclair1.keys().unwrap());
+
+        matrix.press(SpecKey::Num0);
+        joystick.press(JoyInput::Fire);
+        matrix.release(SpecKey::Num0);
+        // Fire is 0 on Sinclair port 1, half-row 4
+        assert_eq!(matrix.read(0xEF), 0b00011110);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::common::read_lines;
+use crate::keyboard::{KeyboardMatrix, SpecKey};
+use crate::keyboard::SpecKey::*;
+
+/// Host keys (by SDL key name) to the Spectrum keys they press, so PC typing habits just work
+///
+/// `"` on the host is SYMBOL SHIFT + P rather than CAPS SHIFT + 1, Backspace is DELETE (CAPS SHIFT + 0) and so on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    /// Keyed by host key name and whether Shift is held
+    entries: HashMap<(String, bool), Vec<SpecKey>>
+}
+
+/// The host key name, the keys it presses unshifted, and with Shift held, for a US layout
+static SMART: &[(&str, &[SpecKey], &[SpecKey])] = &[
+    ("1", &[Num1], &[SymbolShift, Num1]),
+    ("2", &[Num2], &[SymbolShift, Num2]),
+    ("3", &[Num3], &[SymbolShift, Num3]),
+    ("4", &[Num4], &[SymbolShift, Num4]),
+    ("5", &[Num5], &[SymbolShift, Num5]),
+    ("6", &[Num6], &[SymbolShift, H]),
+    ("7", &[Num7], &[SymbolShift, Num6]),
+    ("8", &[Num8], &[SymbolShift, B]),
+    ("9", &[Num9], &[SymbolShift, Num8]),
+    ("0", &[Num0], &[SymbolShift, Num9]),
+    ("'", &[SymbolShift, Num7], &[SymbolShift, P]),
+    (",", &[SymbolShift, N], &[SymbolShift, R]),
+    (".", &[SymbolShift, M], &[SymbolShift, T]),
+    ("/", &[SymbolShift, V], &[SymbolShift, C]),
+    (";", &[SymbolShift, O], &[SymbolShift, Z]),
+    ("=", &[SymbolShift, L], &[SymbolShift, K]),
+    ("-", &[SymbolShift, J], &[SymbolShift, Num0]),
+    ("Backspace", &[CapsShift, Num0], &[CapsShift, Num0]),
+    ("Left", &[CapsShift, Num5], &[CapsShift, Num5]),
+    ("Down", &[CapsShift, Num6], &[CapsShift, Num6]),
+    ("Up", &[CapsShift, Num7], &[CapsShift, Num7]),
+    ("Right", &[CapsShift, Num8], &[CapsShift, Num8]),
+    ("Return", &[Enter], &[Enter]),
+    ("Keypad Enter", &[Enter], &[Enter]),
+    ("Space", &[Space], &[Space]),
+    // BREAK, EXTEND MODE and CAPS LOCK
+    ("Escape", &[CapsShift, Space], &[CapsShift, Space]),
+    ("Tab", &[CapsShift, SymbolShift], &[CapsShift, SymbolShift]),
+    ("CapsLock", &[CapsShift, Num2], &[CapsShift, Num2]),
+    // On their own the shifts still work, for games that use them as keys
+    ("Left Shift", &[CapsShift], &[CapsShift]),
+    ("Right Shift", &[CapsShift], &[CapsShift]),
+    ("Left Ctrl", &[SymbolShift], &[SymbolShift]),
+    ("Right Ctrl", &[SymbolShift], &[SymbolShift])
+];
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::smart()
+    }
+}
+
+impl KeyMap {
+    pub fn smart() -> KeyMap {
+        let mut entries = HashMap::new();
+
+        for (name, plain, shifted) in SMART {
+            entries.insert((name.to_string(), false), plain.to_vec());
+            entries.insert((name.to_string(), true), shifted.to_vec());
+        }
+
+        // Shifted letters are capitals
+        for c in 'A'..='Z' {
+            let key: SpecKey = c.to_string().parse().unwrap();
+            entries.insert((c.to_string(), false), vec![key]);
+            entries.insert((c.to_string(), true), vec![CapsShift, key]);
+        }
+
+        KeyMap { entries }
+    }
+
+    /// The smart map with the entries from a key map file on top
+    ///
+    /// One mapping per line, `HostKey = KEY + KEY`, with host keys named as SDL names them and an optional `Shift+`
+    /// in front. Spectrum keys are letters, digits, CAPS, SYM, ENTER and SPACE. `#` starts a comment
+    pub fn load(path: &Path) -> std::io::Result<KeyMap> {
+        let mut map = KeyMap::smart();
+        read_lines(path, |line| map.parse_line(line))?;
+        Ok(map)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        // The last " = " splits them since host keys can be = themselves, Spectrum keys never are
+        let split = line.rfind(" = ").ok_or_else(|| format!("Expected HostKey = KEY in \"{}\"", line))?;
+        let (host, spectrum) = (line[..split].trim(), &line[split + 3..]);
+
+        let (host, shift) = match host.strip_prefix("Shift+") {
+            Some(host) => (host, true),
+            None => (host, false)
+        };
+
+        let keys = spectrum.split('+').map(|k| k.trim().parse()).collect::<Result<Vec<SpecKey>, _>>()?;
+        self.entries.insert((host.to_string(), shift), keys);
+
+        Ok(())
+    }
+
+    pub fn get(&self, host: &str, shift: bool) -> Option<&[SpecKey]> {
+        self.entries.get(&(host.to_string(), shift)).map(|k| k.as_slice())
+    }
+}
+
+/// Feeds host key presses through a `KeyMap` into the matrix
+///
+/// Keeps track of what each held host key pressed, so overlapping combinations release cleanly
+#[derive(Debug, Clone, Default)]
+pub struct SmartKeyboard {
+    map: KeyMap,
+    /// What each held host key pressed, and whether it was looked up shifted
+    held: HashMap<String, (Vec<SpecKey>, bool)>,
+    /// Everything the last sync pressed, so keys pressed by anything else (like a controller) are left alone
+    pressed: Vec<SpecKey>
+}
+
+impl SmartKeyboard {
+    pub fn new(map: KeyMap) -> SmartKeyboard {
+        SmartKeyboard {
+            map,
+            held: HashMap::new(),
+            pressed: Vec::new()
+        }
+    }
+
+    /// Keys the map doesn't know about are ignored
+    pub fn key_down(&mut self, host: &str, shift: bool, matrix: &KeyboardMatrix) {
+        let found = self.map.get(host, shift).map(|k| (k, shift))
+            .or_else(|| self.map.get(host, false).map(|k| (k, false)));
+
+        if let Some((keys, shifted)) = found {
+            self.held.insert(host.to_string(), (keys.to_vec(), shifted));
+            self.sync(matrix);
+        }
+    }
+
+    pub fn key_up(&mut self, host: &str, matrix: &KeyboardMatrix) {
+        if self.held.remove(host).is_some() {
+            self.sync(matrix);
+        }
+    }
+
+    pub fn release_all(&mut self, matrix: &KeyboardMatrix) {
+        self.held.clear();
+        self.sync(matrix);
+    }
+
+    fn sync(&mut self, matrix: &KeyboardMatrix) {
+        // A shifted mapping already says exactly what it needs, the host Shift that got us there mustn't add CAPS SHIFT
+        let shifted = self.held.iter().any(|(host, (_, shifted))| *shifted && !host.ends_with("Shift"));
+
+        for key in self.pressed.drain(..) {
+            matrix.release(key);
+        }
+        for (host, (keys, _)) in &self.held {
+            if shifted && host.ends_with("Shift") {
+                continue;
+            }
+            for key in keys {
+                matrix.press(*key);
+                self.pressed.push(*key);
+            }
+        }
+    }
+}
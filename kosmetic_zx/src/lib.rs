@@ -3,8 +3,25 @@ pub mod bus;
 pub mod memory;
 pub mod cpu;
 pub mod ula;
+pub mod ulaplus;
+pub mod keyboard;
+pub mod keymap;
+pub mod autotype;
+pub mod joystick;
+pub mod kempston;
+pub mod mouse;
 pub mod clock;
+#[cfg(feature = "sdl")]
 pub mod video;
+#[cfg(feature = "sdl")]
+pub mod controller;
+pub mod frame;
+pub mod palette;
+pub mod filter;
+pub mod blend;
+pub mod machine;
+pub mod screenshot;
+pub mod capture;
 
 #[cfg(feature = "trace-deps")]
 extern crate tracing;
\ No newline at end of file
@@ -0,0 +1,261 @@
+use std::thread::JoinHandle;
+use std::time::Duration;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::bus::{Bus, BusMessage};
+use crate::clock::{Clock, ClockMessage};
+use crate::common::Byte;
+use crate::cpu::{InterruptLine, RefreshLine};
+use crate::frame::Frame;
+use crate::joystick::{JoystickInterface, JoystickState};
+use crate::kempston::Kempston;
+use crate::keyboard::KeyboardMatrix;
+use crate::mouse::{KempstonMouse, MouseState};
+use crate::memory::cpumem::CPURam;
+use crate::memory::fill::RamFill;
+use crate::memory::rom::Rom;
+use crate::memory::ulamem::ULARam;
+use crate::ula::{Ula, UlaConfig};
+use crate::ulaplus::{UlaPlus, UlaPlusPalette};
+
+#[derive(Debug, Clone)]
+pub struct MachineConfig {
+    pub rom: [Byte; 0x4000],
+    /// An Interface 2 cartridge, mapped over the ROM, see `rom::load_cartridge`
+    pub cartridge: Option<[Byte; 0x4000]>,
+    pub ram_fill: RamFill,
+    pub ula: UlaConfig,
+    /// Put the ULAplus ports on the bus
+    pub ulaplus: bool,
+    /// Where `Machine::joystick` is plugged in
+    pub joystick: JoystickInterface,
+    /// Put a Kempston mouse on the bus, reading `Machine::mouse`
+    pub mouse: bool
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        MachineConfig {
+            rom: [0; 0x4000],
+            cartridge: None,
+            ram_fill: RamFill::default(),
+            ula: UlaConfig::default(),
+            ulaplus: false,
+            joystick: JoystickInterface::default(),
+            mouse: false
+        }
+    }
+}
+
+/// A complete 48K machine with every device on the bus and the clock running
+///
+/// Nothing here needs a display, frontends (or tests) read finished frames and their audio from `frames`
+pub struct Machine {
+    pub bus: Sender<BusMessage>,
+    pub ula_ram: Sender<BusMessage>,
+    pub int_line: InterruptLine,
+    /// The ULA watches it for snow, nothing drives it until there's a CPU
+    pub refresh_line: RefreshLine,
+    /// Press and release keys here, the ULA reads it through port 0xFE
+    pub keyboard: KeyboardMatrix,
+    /// The host joystick, whatever interface is plugged in reads it
+    pub joystick: JoystickState,
+    pub mouse: MouseState,
+    pub frames: Receiver<Frame>,
+    clock_comm: Sender<ClockMessage>,
+    clock: Option<JoinHandle<()>>
+}
+
+impl Machine {
+    pub fn new(config: MachineConfig) -> Machine {
+        let bus = Bus::new();
+
+        // Separate chips on a real board, so random contents shouldn't repeat between them
+        let cpuram = CPURam::new(config.ram_fill.for_chip(0));
+        let ula_ram = ULARam::new(config.ram_fill.for_chip(1));
+        let rom = Rom::new(config.cartridge.unwrap_or(config.rom));
+
+        let int_line = InterruptLine::new();
+        let refresh_line = RefreshLine::new();
+        let joystick = JoystickState::new();
+        let mouse = MouseState::new();
+        let keyboard = match config.joystick.keys() {
+            Some(keys) => KeyboardMatrix::with_joystick(joystick.clone(), keys),
+            None => KeyboardMatrix::new()
+        };
+
+        // Without the ports nothing can turn the palette on, so it stays at its disabled default
+        let (ulaplus, ulaplus_palette) = if config.ulaplus {
+            let (device, palette) = UlaPlus::new();
+            (Some(device), palette)
+        } else {
+            (None, UlaPlusPalette::default())
+        };
+
+        let (ula_clock, ula_bus, frames) = Ula::new(bus.clone(), ula_ram.clone(), int_line.clone(), refresh_line.clone(), keyboard.clone(), ulaplus_palette, config.ula);
+        let (cpu_clock, _) = bounded(128);
+        let (clock_comm, clock_comm_rx) = bounded(1);
+
+        Self::add_device(&bus, cpuram);
+        Self::add_device(&bus, ula_ram.clone());
+        Self::add_device(&bus, rom);
+        Self::add_device(&bus, ula_bus);
+        if let Some(ulaplus) = ulaplus {
+            Self::add_device(&bus, ulaplus);
+        }
+        if config.joystick == JoystickInterface::Kempston {
+            Self::add_device(&bus, Kempston::new(joystick.clone()));
+        }
+        if config.mouse {
+            Self::add_device(&bus, KempstonMouse::new(mouse.clone()));
+        }
+
+        let clock = Clock::new(cpu_clock, ula_clock, clock_comm_rx);
+
+        Machine {
+            bus,
+            ula_ram,
+            int_line,
+            refresh_line,
+            keyboard,
+            joystick,
+            mouse,
+            frames,
+            clock_comm,
+            clock: Some(clock)
+        }
+    }
+
+    pub fn add_device(bus: &Sender<BusMessage>, device: Sender<BusMessage>) {
+        let (tx, rx) = bounded(1);
+        bus.send(BusMessage::AddDevice(device, tx)).unwrap();
+        match rx.recv().unwrap() {
+            BusMessage::AddDeviceOk => {},
+            _ => panic!("Couldn't add device to bus")
+        }
+    }
+
+    /// Stops the clock, the ULA finishes its current frame and `frames` disconnects
+    ///
+    /// Frames that haven't been received yet are thrown away, the ULA could be waiting to hand one over
+    pub fn stop(&mut self) {
+        if let Some(clock) = self.clock.take() {
+            let _ = self.clock_comm.send(ClockMessage::Stop);
+            while !clock.is_finished() {
+                let _ = self.frames.recv_timeout(Duration::from_millis(10));
+            }
+            let _ = clock.join();
+        }
+    }
+}
+
+impl Drop for Machine {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(machine: &Machine, message: impl FnOnce(Sender<BusMessage>) -> BusMessage) {
+        let (tx, rx) = bounded(1);
+        machine.bus.send(message(tx)).unwrap();
+        match rx.recv().unwrap() {
+            BusMessage::MemWriteOk | BusMessage::IOWriteOk => {}
+            other => panic!("Write failed with {:?}", other)
+        }
+    }
+
+    fn read(machine: &Machine, port: u16) -> BusMessage {
+        let (tx, rx) = bounded(1);
+        machine.bus.send(BusMessage::IOGet(port, tx)).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// A machine that holds every frame until it's taken
+    fn waiting_machine() -> Machine {
+        let mut config = MachineConfig::default();
+        config.ula.wait_for_frames = true;
+        Machine::new(config)
+    }
+
+    fn frames(machine: &Machine, count: usize) -> Vec<Frame> {
+        (0..count).map(|_| machine.frames.recv().unwrap()).collect()
+    }
+
+    #[test]
+    fn frames_are_numbered_and_carry_a_frame_of_audio() {
+        let machine = waiting_machine();
+
+        for (i, frame) in frames(&machine, 3).iter().enumerate() {
+            assert_eq!(frame.number, i as u64);
+            assert_eq!((frame.width, frame.height), (352, 296));
+            // 69888 T-states at 3.5MHz is 880.6 samples at 44.1kHz
+            assert!((880..=881).contains(&frame.samples.len()), "{} samples", frame.samples.len());
+        }
+    }
+
+    #[test]
+    fn no_frames_are_dropped_for_a_slow_frontend() {
+        let machine = waiting_machine();
+
+        // Don't take anything until the channel is full, so the ULA has to wait with the next frame
+        while !machine.frames.is_full() {
+            std::thread::yield_now();
+        }
+        // The bus still gets answered while the ULA is holding a frame
+        assert!(matches!(read(&machine, 0xFEFE), BusMessage::IOReadOk(_)));
+
+        let numbers: Vec<u64> = frames(&machine, 4).iter().map(|f| f.number).collect();
+        assert_eq!(numbers, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn border_and_display_are_drawn() {
+        let machine = Machine::new(MachineConfig::default());
+
+        write(&machine, |s| BusMessage::IOPut(0x00FE, 0b010, s));
+        write(&machine, |s| BusMessage::MemPut(0x4000, 0xF0, s));
+        // INK 5 on PAPER 2
+        write(&machine, |s| BusMessage::MemPut(0x5800, 0b00010101, s));
+
+        let frame = frames(&machine, 2).pop().unwrap();
+        // The display starts 48 pixels in from both edges of the normal border
+        assert_eq!(frame.get(0, 0), 2);
+        assert_eq!(frame.get(47, 48), 2);
+        assert_eq!(frame.get(48, 48), 5);
+        assert_eq!(frame.get(51, 48), 5);
+        assert_eq!(frame.get(52, 48), 2);
+        assert_eq!(frame.get(48, 49), 2);
+        assert_eq!(frame.get(56, 48), 0);
+    }
+
+    #[test]
+    fn snow_only_hits_the_fetch_that_meets_a_refresh() {
+        let machine = Machine::new(MachineConfig::default());
+
+        write(&machine, |s| BusMessage::MemPut(0x4022, 0xFF, s));
+        write(&machine, |s| BusMessage::MemPut(0x5800, 0b00010101, s));
+        write(&machine, |s| BusMessage::MemPut(0x5801, 0b00010101, s));
+        // 14336 is the bitmap fetch for the top left cell, R replaces the low byte of 0x4000
+        machine.refresh_line.refresh(14336, 0x40, 0x22);
+
+        let frame = frames(&machine, 2).pop().unwrap();
+        assert_eq!(frame.get(48, 48), 5);
+        assert_eq!(frame.get(55, 48), 5);
+        // The next cell along and the line below it are fetched on other T-states
+        assert_eq!(frame.get(56, 48), 2);
+        assert_eq!(frame.get(48, 49), 2);
+    }
+
+    #[test]
+    fn stopping_disconnects_the_frames() {
+        let mut machine = waiting_machine();
+        frames(&machine, 1);
+        machine.stop();
+
+        while machine.frames.recv().is_ok() {}
+        assert!(machine.frames.recv().is_err());
+    }
+}
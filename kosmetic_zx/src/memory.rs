@@ -1,3 +1,4 @@
 pub mod ulamem;
 pub mod rom;
-pub mod cpumem;
\ No newline at end of file
+pub mod cpumem;
+pub mod fill;
\ No newline at end of file
@@ -3,6 +3,7 @@ use crossbeam_channel::{Receiver, Sender, bounded};
 use std::thread;
 use crate::common::{Byte};
 use crate::bus::{BusMessage, Range};
+use crate::memory::fill::RamFill;
 
 #[cfg(feature = "trace-memory")]
 use tracing::*;
@@ -14,13 +15,14 @@ pub struct CPURam {
 }
 
 impl CPURam {
-    pub fn new() -> Sender<BusMessage> {
+    pub fn new(fill: RamFill) -> Sender<BusMessage> {
         let (tx, rx) = bounded(128);
         thread::spawn( move || {
             let mut ram = CPURam {
                 bytes: [0; 0x8000],
                 receiver: rx
             };
+            fill.fill(&mut ram.bytes);
             ram.message_loop();
         });
 
@@ -28,25 +30,25 @@ impl CPURam {
     }
 
     fn message_loop(&mut self) {
-        loop {
-            match self.receiver.recv().unwrap() {
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
                 BusMessage::MemPut(a, b, s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Write to CPUMem").enter();
                     self.bytes[a as usize] = b;
-                    s.send(BusMessage::MemWriteOk).unwrap();
+                    let _ = s.send(BusMessage::MemWriteOk);
                 },
                 BusMessage::MemGet(a, s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Read from CPUMem").enter();
-                    s.send(BusMessage::MemReadOk(self.bytes[a as usize])).unwrap();
+                    let _ = s.send(BusMessage::MemReadOk(self.bytes[a as usize]));
                 },
-                BusMessage::IOPut(_, _, s) => s.send(BusMessage::Err).unwrap(),
-                BusMessage::IOGet(_, s) => s.send(BusMessage::Err).unwrap(),
+                BusMessage::IOPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOGet(_, s) => { let _ = s.send(BusMessage::Err); },
                 BusMessage::GetRanges(s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Send CPUMem memory ranges").enter();
-                    s.send(BusMessage::RangesRet(vec![Range(0x8000,0xFFFF)],vec![Range(0x8000,0xFFFF)],vec![],vec![])).unwrap();
+                    let _ = s.send(BusMessage::RangesRet(vec![Range(0x8000,0xFFFF)],vec![Range(0x8000,0xFFFF)],vec![],vec![]));
                 },
                 _ => {}
             }
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::common::Byte;
+
+/// What a RAM chip holds when the machine is powered on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamFill {
+    /// Every byte is 0x00
+    #[default]
+    Zero,
+    /// Every byte is 0xFF
+    Ones,
+    /// Bytes alternate between the two given values
+    Alternating(Byte, Byte),
+    /// Pseudo-random bytes, the same seed always gives the same contents
+    Random(u64),
+}
+
+impl RamFill {
+    /// The policy for one of several RAM chips, so that random contents differ between chips that share a seed
+    pub fn for_chip(&self, chip: u64) -> RamFill {
+        match *self {
+            RamFill::Random(seed) => RamFill::Random(splitmix64(seed.wrapping_add(chip.wrapping_mul(0x9E37_79B9_7F4A_7C15)))),
+            fill => fill
+        }
+    }
+
+    pub fn fill(&self, bytes: &mut [Byte]) {
+        match *self {
+            RamFill::Zero => bytes.fill(0x00),
+            RamFill::Ones => bytes.fill(0xFF),
+            RamFill::Alternating(a, b) => {
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = if i % 2 == 0 { a } else { b };
+                }
+            }
+            RamFill::Random(seed) => {
+                // xorshift64*, a zero state would only ever produce zeroes
+                let mut state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+                for byte in bytes.iter_mut() {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *byte = (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as Byte;
+                }
+            }
+        }
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Parses `zero`, `ones`, `alternating[:AA:BB]` or `random[:seed]`
+///
+/// A bare `random` picks its seed from the time, print the parsed value to be able to repeat the run
+impl FromStr for RamFill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or("");
+        let args: Vec<&str> = parts.collect();
+
+        match (kind, args.as_slice()) {
+            ("zero", []) => Ok(RamFill::Zero),
+            ("ones", []) => Ok(RamFill::Ones),
+            ("alternating", []) => Ok(RamFill::Alternating(0x00, 0xFF)),
+            ("alternating", [a, b]) => Ok(RamFill::Alternating(
+                Byte::from_str_radix(a, 16).map_err(|e| e.to_string())?,
+                Byte::from_str_radix(b, 16).map_err(|e| e.to_string())?,
+            )),
+            ("random", []) => Ok(RamFill::Random(
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+            )),
+            ("random", [seed]) => Ok(RamFill::Random(seed.parse().map_err(|e: std::num::ParseIntError| e.to_string())?)),
+            _ => Err(format!("Unknown RAM fill policy \"{}\"", s)),
+        }
+    }
+}
+
+/// Writes the policy back in the form `FromStr` takes
+impl fmt::Display for RamFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RamFill::Zero => write!(f, "zero"),
+            RamFill::Ones => write!(f, "ones"),
+            RamFill::Alternating(a, b) => write!(f, "alternating:{:02X}:{:02X}", a, b),
+            RamFill::Random(seed) => write!(f, "random:{}", seed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(fill: RamFill) -> Vec<Byte> {
+        let mut bytes = vec![0x55; 16];
+        fill.fill(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn parses_every_policy() {
+        assert_eq!("zero".parse(), Ok(RamFill::Zero));
+        assert_eq!("ones".parse(), Ok(RamFill::Ones));
+        assert_eq!("alternating".parse(), Ok(RamFill::Alternating(0x00, 0xFF)));
+        assert_eq!("alternating:AA:0f".parse(), Ok(RamFill::Alternating(0xAA, 0x0F)));
+        assert_eq!("random:1234".parse(), Ok(RamFill::Random(1234)));
+        assert!(matches!("random".parse(), Ok(RamFill::Random(_))));
+    }
+
+    #[test]
+    fn rejects_bad_policies() {
+        for policy in ["", "twos", "zero:1", "alternating:AA", "alternating:AA:GG", "random:x", "random:1:2"] {
+            assert!(policy.parse::<RamFill>().is_err(), "{} parsed", policy);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for fill in [RamFill::Zero, RamFill::Ones, RamFill::Alternating(0x12, 0xEF), RamFill::Random(u64::MAX)] {
+            assert_eq!(fill.to_string().parse(), Ok(fill));
+        }
+    }
+
+    #[test]
+    fn fills_constant_patterns() {
+        assert_eq!(filled(RamFill::Zero), vec![0x00; 16]);
+        assert_eq!(filled(RamFill::Ones), vec![0xFF; 16]);
+        assert_eq!(filled(RamFill::Alternating(0x12, 0x34))[..4], [0x12, 0x34, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn random_fill_depends_only_on_the_seed() {
+        assert_eq!(filled(RamFill::Random(7)), filled(RamFill::Random(7)));
+        assert_ne!(filled(RamFill::Random(7)), filled(RamFill::Random(8)));
+        // Zero is a fixed point of xorshift, it still has to give something random looking
+        assert!(filled(RamFill::Random(0)).iter().any(|b| *b != 0));
+    }
+
+    #[test]
+    fn chips_get_their_own_random_contents() {
+        let fill = RamFill::Random(42);
+        assert_ne!(filled(fill.for_chip(0)), filled(fill.for_chip(1)));
+        assert_eq!(filled(fill.for_chip(1)), filled(RamFill::Random(42).for_chip(1)));
+        assert_eq!(RamFill::Ones.for_chip(1), RamFill::Ones);
+    }
+}
@@ -1,6 +1,8 @@
 use std::sync::mpsc;
 use crossbeam_channel::{Receiver, Sender, bounded};
 use std::thread;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
 use crate::common::{Byte};
 use crate::bus::{BusMessage, Range};
 
@@ -13,6 +15,18 @@ pub struct Rom {
     pub(crate) receiver: Receiver<BusMessage>
 }
 
+/// Interface 2 cartridges are at most 16K, anything shorter leaves the rest of the ROM space floating
+pub fn load_cartridge(path: &Path) -> std::io::Result<[Byte; 0x4000]> {
+    let data = std::fs::read(path)?;
+    if data.len() > 0x4000 {
+        return Err(Error::new(ErrorKind::InvalidData, "Cartridges can't be bigger than 16K"));
+    }
+
+    let mut contents = [0xFF; 0x4000];
+    contents[..data.len()].copy_from_slice(&data);
+    Ok(contents)
+}
+
 impl Rom {
     pub fn new(contents: [Byte; 0x4000]) -> Sender<BusMessage> {
         let (tx, rx) = bounded(128);
@@ -30,20 +44,20 @@ impl Rom {
     }
 
     fn message_loop(&mut self) {
-        loop {
-            match self.receiver.recv().unwrap() {
-                BusMessage::MemPut(_, _, s) => s.send(BusMessage::Err).unwrap(),
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
+                BusMessage::MemPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
                 BusMessage::MemGet(a, s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Read from Rom").enter();
-                    s.send(BusMessage::MemReadOk(self.contents[a as usize])).unwrap();
+                    let _ = s.send(BusMessage::MemReadOk(self.contents[a as usize]));
                 },
-                BusMessage::IOPut(_, _, s) => s.send(BusMessage::Err).unwrap(),
-                BusMessage::IOGet(_, s) => s.send(BusMessage::Err).unwrap(),
+                BusMessage::IOPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOGet(_, s) => { let _ = s.send(BusMessage::Err); },
                 BusMessage::GetRanges(s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Send ROM memory ranges").enter();
-                    s.send(BusMessage::RangesRet(vec![Range(0x0000,0x3FFF)],vec![Range(0x0000,0x3FFF)],vec![],vec![])).unwrap();
+                    let _ = s.send(BusMessage::RangesRet(vec![Range(0x0000,0x3FFF)],vec![Range(0x0000,0x3FFF)],vec![],vec![]));
                 },
                 _ => {}
             }
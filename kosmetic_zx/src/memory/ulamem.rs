@@ -3,10 +3,12 @@ use crossbeam_channel::{Receiver, Sender, bounded};
 use std::thread;
 use crate::common::{Byte};
 use crate::bus::{BusMessage, Range};
+use crate::memory::fill::RamFill;
 
 #[cfg(feature = "trace-memory")]
 use tracing::*;
 
+/// 0x4000 - 0x7FFF, holds both Timex display files (0x4000 and 0x6000) so the ULA can reach either
 #[derive(Debug)]
 pub struct ULARam {
     pub(crate) bytes: [Byte; 0x4000],
@@ -14,13 +16,14 @@ pub struct ULARam {
 }
 
 impl ULARam {
-    pub fn new() -> Sender<BusMessage> {
+    pub fn new(fill: RamFill) -> Sender<BusMessage> {
         let (tx, rx) = bounded(128);
         thread::spawn( move || {
             let mut ula = ULARam {
                 bytes: [0; 0x4000],
                 receiver: rx
             };
+            fill.fill(&mut ula.bytes);
 
             ula.message_loop();
         });
@@ -29,25 +32,25 @@ impl ULARam {
     }
 
     fn message_loop(&mut self) {
-        loop {
-            match self.receiver.recv().unwrap() {
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
                 BusMessage::MemPut(a, b, s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Write to ULAMem").enter();
                     self.bytes[a as usize] = b;
-                    s.send(BusMessage::MemWriteOk).unwrap();
+                    let _ = s.send(BusMessage::MemWriteOk);
                 },
                 BusMessage::MemGet(a, s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Read from ULAMem").enter();
-                    s.send(BusMessage::MemReadOk(self.bytes[a as usize])).unwrap();
+                    let _ = s.send(BusMessage::MemReadOk(self.bytes[a as usize]));
                 },
-                BusMessage::IOPut(_, _, s) => s.send(BusMessage::Err).unwrap(),
-                BusMessage::IOGet(_, s) => s.send(BusMessage::Err).unwrap(),
+                BusMessage::IOPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOGet(_, s) => { let _ = s.send(BusMessage::Err); },
                 BusMessage::GetRanges(s) => {
                     #[cfg(feature = "trace-memory")]
                         let _ = span!(Level::TRACE, "Send ULARam memory ranges").enter();
-                    s.send(BusMessage::RangesRet(vec![Range(0x4000,0x7FFF)],vec![Range(0x4000,0x7FFF)],vec![],vec![])).unwrap();
+                    let _ = s.send(BusMessage::RangesRet(vec![Range(0x4000,0x7FFF)],vec![Range(0x4000,0x7FFF)],vec![],vec![]));
                 },
                 _ => {}
             }
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::thread;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::bus::{BusMessage, Port};
+use crate::common::Byte;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Right = 0b001,
+    Left = 0b010,
+    Middle = 0b100
+}
+
+#[derive(Debug, Default)]
+struct MouseCounters {
+    x: AtomicU8,
+    y: AtomicU8,
+    /// `MouseButton` bits, set while held
+    buttons: AtomicU8
+}
+
+/// The host mouse, shared between the frontend and the interface
+#[derive(Debug, Clone, Default)]
+pub struct MouseState(Arc<MouseCounters>);
+
+impl MouseState {
+    pub fn new() -> MouseState {
+        MouseState::default()
+    }
+
+    /// Positive `dy` is up, the counters just wrap like the real ones
+    pub fn move_by(&self, dx: i32, dy: i32) {
+        let _ = self.0.x.fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| Some((x as i32 + dx) as Byte));
+        let _ = self.0.y.fetch_update(Ordering::AcqRel, Ordering::Acquire, |y| Some((y as i32 + dy) as Byte));
+    }
+
+    pub fn press(&self, button: MouseButton) {
+        self.0.buttons.fetch_or(button as Byte, Ordering::AcqRel);
+    }
+
+    pub fn release(&self, button: MouseButton) {
+        self.0.buttons.fetch_and(!(button as Byte), Ordering::AcqRel);
+    }
+
+    pub fn release_all(&self) {
+        self.0.buttons.store(0, Ordering::Release);
+    }
+
+    pub fn x(&self) -> Byte {
+        self.0.x.load(Ordering::Acquire)
+    }
+
+    pub fn y(&self) -> Byte {
+        self.0.y.load(Ordering::Acquire)
+    }
+
+    /// Active low, the unused bits read 1
+    pub fn buttons(&self) -> Byte {
+        !self.0.buttons.load(Ordering::Acquire)
+    }
+}
+
+/// Buttons at 0xFADF, X at 0xFBDF and Y at 0xFFDF, decoded on A0, A5, A7, A8 and A10
+pub struct KempstonMouse {
+    state: MouseState,
+    receiver: Receiver<BusMessage>
+}
+
+impl KempstonMouse {
+    /// A7 high as well on every port, otherwise Kempston joystick reads like 0x1F or 0x011F would reach the mouse
+    fn buttons_port() -> Port {
+        Port::new(0x01A1, 0x0081)
+    }
+
+    fn x_port() -> Port {
+        Port::new(0x05A1, 0x0181)
+    }
+
+    fn y_port() -> Port {
+        Port::new(0x05A1, 0x0581)
+    }
+
+    pub fn new(state: MouseState) -> Sender<BusMessage> {
+        let (tx, rx) = bounded(128);
+
+        thread::spawn(move || {
+            let mut mouse = KempstonMouse {
+                state,
+                receiver: rx
+            };
+
+            mouse.message_loop();
+        });
+
+        tx
+    }
+
+    fn message_loop(&mut self) {
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
+                BusMessage::MemPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::MemGet(_, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOGet(a, s) => {
+                    let b = if Self::x_port().matches(a) {
+                        self.state.x()
+                    } else if Self::y_port().matches(a) {
+                        self.state.y()
+                    } else {
+                        self.state.buttons()
+                    };
+                    let _ = s.send(BusMessage::IOReadOk(b));
+                },
+                BusMessage::GetRanges(s) => {
+                    let ports = vec![Self::buttons_port(), Self::x_port(), Self::y_port()];
+                    let _ = s.send(BusMessage::RangesRet(vec![], vec![], ports, vec![]));
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Only the three buttons the interface has
+#[cfg(feature = "sdl")]
+pub fn from_sdl_button(button: sdl2::mouse::MouseButton) -> Option<MouseButton> {
+    match button {
+        sdl2::mouse::MouseButton::Left => Some(MouseButton::Left),
+        sdl2::mouse::MouseButton::Right => Some(MouseButton::Right),
+        sdl2::mouse::MouseButton::Middle => Some(MouseButton::Middle),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::common::Address;
+    use crate::joystick::{JoyInput, JoystickState};
+    use crate::kempston::Kempston;
+
+    fn io(bus: &Sender<BusMessage>, message: impl FnOnce(Sender<BusMessage>) -> BusMessage) -> BusMessage {
+        let (tx, rx) = bounded(1);
+        bus.send(message(tx)).unwrap();
+        rx.recv().unwrap()
+    }
+
+    fn read(bus: &Sender<BusMessage>, port: Address) -> Option<Byte> {
+        match io(bus, |s| BusMessage::IOGet(port, s)) {
+            BusMessage::IOReadOk(b) => Some(b),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn shares_the_bus_with_a_kempston_joystick() {
+        let bus = Bus::new();
+        let joystick = JoystickState::new();
+        let mouse = MouseState::new();
+        io(&bus, |s| BusMessage::AddDevice(Kempston::new(joystick.clone()), s));
+        io(&bus, |s| BusMessage::AddDevice(KempstonMouse::new(mouse.clone()), s));
+
+        joystick.press(JoyInput::Fire);
+        mouse.move_by(5, -3);
+        mouse.press(MouseButton::Left);
+
+        // Joystick reads that happen to set the mouse's A8 or A10 still have A7 low
+        for port in [0x001F, 0x011F, 0x051F] {
+            assert_eq!(read(&bus, port), Some(0b10000), "port {:04X}", port);
+        }
+        assert_eq!(read(&bus, 0xFBDF), Some(5));
+        assert_eq!(read(&bus, 0xFFDF), Some(-3_i8 as Byte));
+        assert_eq!(read(&bus, 0xFADF), Some(!(MouseButton::Left as Byte)));
+    }
+}
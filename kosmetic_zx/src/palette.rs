@@ -0,0 +1,187 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use crate::frame::{Pixel, ULAPLUS_PIXEL};
+use crate::ulaplus::grb332_to_rgb;
+
+/// RGB values for the 16 colour indices a `Frame` is made of, 0-7 normal and 8-15 BRIGHT
+///
+/// ULAplus colours are already RGB and pass straight through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [[u8; 3]; 16]
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::saturated()
+    }
+}
+
+impl Palette {
+    /// Builds the 16 colours from the level a normal and a BRIGHT channel is driven to
+    fn from_levels(name: &str, normal: u8, bright: u8) -> Palette {
+        let mut colors = [[0; 3]; 16];
+        for (index, color) in colors.iter_mut().enumerate() {
+            let i = if index & 0b00001000 != 0 { bright } else { normal };
+            *color = [
+                if index & 0b010 != 0 { i } else { 0 },
+                if index & 0b100 != 0 { i } else { 0 },
+                if index & 0b001 != 0 { i } else { 0 }
+            ];
+        }
+
+        Palette { name: name.to_string(), colors }
+    }
+
+    /// The usual 0xD7 / 0xFF levels
+    pub fn saturated() -> Palette {
+        Palette::from_levels("saturated", 0xD7, 0xFF)
+    }
+
+    /// Measured from the video output of a real issue 3 48K
+    pub fn measured() -> Palette {
+        Palette {
+            name: "measured".to_string(),
+            colors: [
+                [0x00, 0x00, 0x00], [0x01, 0x00, 0xCE], [0xCF, 0x01, 0x00], [0xCF, 0x01, 0xCE],
+                [0x00, 0xCF, 0x15], [0x01, 0xCF, 0xCF], [0xCF, 0xCF, 0x15], [0xCF, 0xCF, 0xCF],
+                [0x00, 0x00, 0x00], [0x02, 0x00, 0xFD], [0xFF, 0x02, 0x01], [0xFF, 0x02, 0xFD],
+                [0x00, 0xFF, 0x1C], [0x02, 0xFF, 0xFF], [0xFF, 0xFF, 0x1D], [0xFF, 0xFF, 0xFF]
+            ]
+        }
+    }
+
+    /// What a black and white TV shows
+    pub fn greyscale() -> Palette {
+        Palette::saturated().map("greyscale", |l| [l, l, l])
+    }
+
+    /// A green phosphor monochrome monitor
+    pub fn green_screen() -> Palette {
+        Palette::saturated().map("green-screen", |l| [l / 5, l, l / 4])
+    }
+
+    /// Every built-in palette, in the order the frontend cycles through them
+    pub fn presets() -> Vec<Palette> {
+        vec![Palette::saturated(), Palette::measured(), Palette::greyscale(), Palette::green_screen()]
+    }
+
+    /// Looks a preset up by name, or failing that loads it as a palette file
+    pub fn find(name: &str) -> std::io::Result<Palette> {
+        match Palette::presets().into_iter().find(|p| p.name == name) {
+            Some(p) => Ok(p),
+            None => Palette::load(Path::new(name))
+        }
+    }
+
+    /// Loads a palette file: 16 `RRGGBB` hex colours (a leading `#` is fine) one per line, in index order
+    ///
+    /// Blank lines and lines starting with `;` are skipped
+    pub fn load(path: &Path) -> std::io::Result<Palette> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), msg));
+
+        let mut colors = [[0; 3]; 16];
+        let mut count = 0;
+
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with(';')) {
+            let hex = line.trim_start_matches('#');
+            if count == 16 {
+                return Err(invalid("more than 16 colours".to_string()));
+            }
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid(format!("\"{}\" isn't an RRGGBB colour", line)));
+            }
+
+            let value = u32::from_str_radix(hex, 16).map_err(|_| invalid(format!("\"{}\" isn't an RRGGBB colour", line)))?;
+            colors[count] = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+            count += 1;
+        }
+
+        if count != 16 {
+            return Err(invalid(format!("expected 16 colours, found {}", count)));
+        }
+
+        Ok(Palette {
+            name: path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+            colors
+        })
+    }
+
+    pub fn rgb(&self, pixel: Pixel) -> [u8; 3] {
+        if pixel & ULAPLUS_PIXEL != 0 {
+            grb332_to_rgb(pixel as u8)
+        } else {
+            self.colors[(pixel & 0x0F) as usize]
+        }
+    }
+
+    fn map(&self, name: &str, f: impl Fn(u8) -> [u8; 3]) -> Palette {
+        let mut colors = self.colors;
+        for color in colors.iter_mut() {
+            let [r, g, b] = *color;
+            let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8;
+            *color = f(luma);
+        }
+
+        Palette { name: name.to_string(), colors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn palette_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kosmetic-{}-{}.pal", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn colours(count: usize) -> String {
+        (0..count).map(|i| format!("#{:02X}{:02X}{:02X}\n", i, i * 2, i * 3)).collect()
+    }
+
+    #[test]
+    fn loads_sixteen_colours() {
+        let text = format!("; Comments and blank lines are skipped\n\n{}", colours(15)) + "  a0B1c2  \n";
+        let path = palette_file("valid", &text);
+        let palette = Palette::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(palette.name, format!("kosmetic-{}-valid", std::process::id()));
+        assert_eq!(palette.colors[0], [0x00, 0x00, 0x00]);
+        assert_eq!(palette.colors[14], [14, 28, 42]);
+        assert_eq!(palette.colors[15], [0xA0, 0xB1, 0xC2]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let cases = [
+            ("short", colours(15)),
+            ("long", colours(17)),
+            ("digits", colours(15) + "12345\n"),
+            ("extra", colours(15) + "1234567\n"),
+            ("hex", colours(15) + "GG0000\n"),
+            ("sign", colours(15) + "+12345\n"),
+        ];
+
+        for (name, text) in cases {
+            let path = palette_file(name, &text);
+            let result = Palette::load(&path);
+            fs::remove_file(&path).unwrap();
+
+            let error = result.expect_err(name);
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn missing_files_are_an_error() {
+        let path = std::env::temp_dir().join("kosmetic-no-such-palette.pal");
+        assert_eq!(Palette::load(&path).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use crossbeam_channel::{bounded, Sender};
+use crate::bus::BusMessage;
+use crate::common::Byte;
+use crate::frame::Image;
+
+/// Bitmap and attributes, 0x4000 - 0x5AFF
+pub static SCR_LENGTH: usize = 6912;
+
+/// Writes the whole frame, border included, as an RGBA PNG
+pub fn save_png(frame: &Image, path: &Path) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, frame.width as u32, frame.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer.write_image_data(&frame.rgba).map_err(Error::other)
+}
+
+/// Reads the display file straight out of ULA RAM (`Machine::ula_ram`), in .SCR order
+pub fn read_scr(ula_ram: &Sender<BusMessage>) -> std::io::Result<Vec<Byte>> {
+    let (tx, rx) = bounded(1);
+    let mut scr = Vec::with_capacity(SCR_LENGTH);
+
+    for address in 0..SCR_LENGTH {
+        ula_ram.send(BusMessage::MemGet(address as u16, tx.clone()))
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "ULA RAM has shut down"))?;
+        match rx.recv() {
+            Ok(BusMessage::MemReadOk(b)) => scr.push(b),
+            _ => return Err(Error::other(format!("Couldn't read ULA RAM at {:#06x}", address + 0x4000)))
+        }
+    }
+
+    Ok(scr)
+}
+
+/// Dumps the 6912 byte display file as a raw .SCR
+pub fn save_scr(ula_ram: &Sender<BusMessage>, path: &Path) -> std::io::Result<()> {
+    let scr = read_scr(ula_ram)?;
+    File::create(path)?.write_all(&scr)
+}
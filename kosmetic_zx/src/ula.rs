@@ -1,148 +1,528 @@
-use std::sync::{Arc, mpsc, Mutex};
+use std::sync::{mpsc};
+use std::str::FromStr;
 use std::thread;
 use std::time::{Instant};
-use sdl2::pixels::Color;
-use sdl2::rect::Point;
-use crate::bus::{BusMessage, Range};
+use crate::bus::{BusMessage, Port};
 use crate::clock::{ClockMessage};
-use crate::common::{Rect, Vec2, Byte};
-use crate::video::VideoLayer;
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::common::{Rect, Vec2, Byte, Address};
+use crate::cpu::{InterruptLine, RefreshLine};
+use crate::keyboard::KeyboardMatrix;
+use crate::frame::{color_index, ulaplus_pixel, Frame, Pixel};
+use crate::ulaplus::UlaPlusPalette;
+use crossbeam_channel::{bounded, Receiver, Select, Sender, TryRecvError};
 
 #[cfg(feature = "trace-ula")]
 use tracing::*;
 
-static BORDER_AREA: Rect = Rect { x: 96, y: 16, w: 352, h: 315 };
-static SCREEN_AREA: Rect = Rect { x: BORDER_AREA.x + 48, y: BORDER_AREA.y + 48, w: 256, h: 192 };
+static T_STATES_PER_LINE: u32 = 224;
+static LINES_PER_FRAME: u32 = 312;
+static T_STATES_PER_FRAME: u32 = T_STATES_PER_LINE * LINES_PER_FRAME;
+/// A raster line starts with its left border, 24 T-states before the first display pixel of the line
+static LEFT_BORDER_T_STATES: u32 = 24;
+
+/// Raster coordinates are in pixels (two per T-state), x = 0 is the start of the left border, y = 0 the interrupt line
+static SCREEN_AREA: Rect = Rect { x: 48, y: 64, w: 256, h: 192 };
+
+/// How much of the raster around the display ends up in each frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BorderSize {
+    /// Just the 256x192 display
+    None,
+    /// 16 pixels all round
+    Small,
+    /// What a typical TV shows, 48 pixels either side
+    #[default]
+    Normal,
+    /// The whole raster, blanking and retrace included
+    Full
+}
+
+impl BorderSize {
+    /// The part of the raster that's drawn, in raster coordinates
+    pub fn area(&self) -> Rect {
+        match self {
+            BorderSize::None => SCREEN_AREA,
+            BorderSize::Small => Rect { x: 32, y: 48, w: 288, h: 224 },
+            BorderSize::Normal => Rect { x: 0, y: 16, w: 352, h: 296 },
+            BorderSize::Full => Rect { x: 0, y: 0, w: (T_STATES_PER_LINE * 2) as u16, h: LINES_PER_FRAME as u16 }
+        }
+    }
+}
+
+impl FromStr for BorderSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(BorderSize::None),
+            "small" => Ok(BorderSize::Small),
+            "normal" => Ok(BorderSize::Normal),
+            "full" => Ok(BorderSize::Full),
+            _ => Err(format!("Unknown border size \"{}\"", s))
+        }
+    }
+}
+
+pub static SAMPLE_RATE: u32 = 44_100;
+static T_STATES_PER_SECOND: u32 = 3_500_000;
+
+pub static INT_LENGTH_48K: u32 = 32;
+pub static INT_LENGTH_128K: u32 = 36;
+
+/// Timex screen modes, bits 0-2 of port 0xFF
+static TIMEX_SCREEN_1: Byte = 0b001;
+static TIMEX_HI_COLOUR: Byte = 0b010;
+static TIMEX_HI_RES: Byte = 0b110;
+/// The second display file, relative to 0x4000 like everything else the ULA reads
+static TIMEX_SCREEN_1_OFFSET: Address = 0x2000;
+
+#[derive(Debug, Clone)]
+pub struct UlaConfig {
+    /// How many T-states /INT is held for at the start of each frame
+    pub int_length: u32,
+    /// Answer port 0xFF like a TC2048/TS2068, frames are rendered at twice the size to fit 512 pixel hi-res lines
+    pub timex: bool,
+    pub border: BorderSize,
+    /// Wait for the frontend to take every frame rather than skipping the ones it isn't ready for, recording and
+    /// headless runs need them all. The bus is still answered while waiting
+    pub wait_for_frames: bool
+}
+
+impl Default for UlaConfig {
+    fn default() -> Self {
+        UlaConfig {
+            int_length: INT_LENGTH_48K,
+            timex: false,
+            border: BorderSize::default(),
+            wait_for_frames: false
+        }
+    }
+}
 
 pub struct Ula {
     bus_control_tx: Sender<BusMessage>,
     bus_rx: Receiver<BusMessage>,
-    video_layer: Option<Arc<Mutex<VideoLayer>>>,
-    border_color: Color,
+    ula_ram: Sender<BusMessage>,
+    int_line: InterruptLine,
+    refresh_line: RefreshLine,
+    keyboard: KeyboardMatrix,
+    ulaplus: UlaPlusPalette,
+    config: UlaConfig,
+    bitmap_latch: Byte,
+    /// The second bitmap byte of a hi-res cell, from the alternate display file
+    hires_latch: Byte,
+    attribute_latch: Byte,
+    ink_latch: Pixel,
+    paper_latch: Pixel,
+    frame: Frame,
+    /// The visible part of the raster line being drawn, copied into `frame` once the line is done
+    line: Vec<Pixel>,
+    frame_tx: Sender<Frame>,
+    /// Bits 0-2 of the last write to port 0xFE
+    border: Byte,
+    border_latch: Pixel,
+    /// Bits 3 (MIC) and 4 (EAR) of the last write to port 0xFE
+    speaker: Byte,
+    /// The last write to port 0xFF, only ever set on a Timex ULA
+    timex_mode: Byte,
+    /// `timex_mode` as it was at the start of the current 8 pixel cell
+    timex_mode_latch: Byte,
+    /// 2 for a Timex ULA, otherwise 1
+    frame_scale: usize,
+    /// `config.border.area()`, looked up once
+    visible: Rect,
+    sample_acc: u32,
+    t_state: u32,
+    second_half: bool,
     render_pos: Vec2,
-    clock_rx: Receiver<ClockMessage>,
-    clock_tx: Sender<ClockMessage>
+    frame_count: u64,
+    clock_rx: Receiver<ClockMessage>
 }
 
 impl Ula {
-    pub fn new(video_layer: Option<()>, bus_sender: Sender<BusMessage>) -> (Sender<ClockMessage>, Sender<BusMessage>, Receiver<ClockMessage>) {
+    /// `ula_ram` is the ULA's own connection to the lower 16K of RAM, display file addresses are relative to 0x4000
+    ///
+    /// Every finished frame is sent down the returned `Receiver<Frame>`. Unless `UlaConfig::wait_for_frames` is set,
+    /// frames the receiver isn't ready for are dropped rather than holding up emulation, `Frame::number` shows any gaps
+    pub fn new(bus_sender: Sender<BusMessage>, ula_ram: Sender<BusMessage>, int_line: InterruptLine, refresh_line: RefreshLine, keyboard: KeyboardMatrix, ulaplus: UlaPlusPalette, config: UlaConfig) -> (Sender<ClockMessage>, Sender<BusMessage>, Receiver<Frame>) {
         let (clock_held_tx, clock_rx) = bounded(128);
         let (bus_tx, bus_rx) = bounded(128);
-        let (clock_tx, clock_held_rx) = bounded(128);
+        let (frame_tx, frame_rx) = bounded(2);
 
         thread::spawn( move || {
+            let frame_scale = if config.timex { 2 } else { 1 };
+            let visible = config.border.area();
             let mut ula = Ula {
                 bus_control_tx: bus_sender.clone(),
                 bus_rx,
-                video_layer: if video_layer.is_some() { Some(VideoLayer::new()) } else { None },
-                border_color: Color::RGB(0, 0, 0),
+                ula_ram,
+                int_line,
+                refresh_line,
+                keyboard,
+                ulaplus,
+                config,
+                bitmap_latch: 0,
+                hires_latch: 0,
+                attribute_latch: 0,
+                ink_latch: 0,
+                paper_latch: 0,
+                frame: Frame::new(visible.w as usize * frame_scale, visible.h as usize * frame_scale, 0),
+                line: vec![0; visible.w as usize * frame_scale],
+                frame_tx,
+                border: 0,
+                border_latch: 0,
+                speaker: 0,
+                timex_mode: 0,
+                timex_mode_latch: 0,
+                frame_scale,
+                visible,
+                sample_acc: 0,
+                t_state: 0,
+                second_half: false,
                 render_pos: Vec2::new(0, 0),
-                clock_rx,
-                clock_tx
+                frame_count: 0,
+                clock_rx
             };
 
             ula.loop_thing()
         });
 
-        (clock_held_tx, bus_tx, clock_held_rx)
+        (clock_held_tx, bus_tx, frame_rx)
     }
 
     pub fn loop_thing(&mut self) {
         loop {
-            let clock_msg = self.clock_rx.try_recv();
-
-            if clock_msg.is_ok() {
-                if clock_msg.unwrap() == ClockMessage::Tick {
-                    self.event_loop();
-                } else { break; }
+            // A clock that went away without saying Stop still means there are no more ticks coming
+            match self.clock_rx.try_recv() {
+                Ok(ClockMessage::Tick) => self.event_loop(),
+                Ok(ClockMessage::Stop) | Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
             }
 
             self.check_message();
         }
     }
 
+    /// Runs one pixel clock (half a T-state) of the 48K raster
     pub fn event_loop(&mut self) {
         #[cfg(feature = "trace-ula")]
             let _ = span!(Level::TRACE, "Run ULA Event loop").enter();
-        if self.video_layer.is_some() {
-            if self.inside(SCREEN_AREA.x, SCREEN_AREA.y, SCREEN_AREA.w, SCREEN_AREA.h, self.render_pos.x, self.render_pos.y, 1, 1) {
-
-            } else if self.inside(BORDER_AREA.x, BORDER_AREA.y, BORDER_AREA.w, BORDER_AREA.h, self.render_pos.x, self.render_pos.y, 1, 1) {
-                self.video_layer.as_ref().unwrap().lock().expect("Couldn't unlock write lock for canvas").canvas.lock().unwrap()
-                    .set_draw_color(self.border_color);
-                self.video_layer.as_ref().unwrap().lock().expect("Couldn't unlock write lock for canvas").canvas.lock().unwrap()
-                    .draw_point(Point::new((self.render_pos.x - BORDER_AREA.x) as i32, (self.render_pos.y - BORDER_AREA.y) as i32)).unwrap();
+
+        if !self.second_half {
+            // Bit 6 of the Timex port stops the ULA from interrupting at all
+            if self.t_state == 0 && self.timex_mode & 0b01000000 == 0 {
+                self.int_line.assert();
+            } else if self.t_state == self.config.int_length {
+                self.int_line.release();
             }
+        }
 
-            self.render_pos.x += 1;
+        let raster_t_state = (self.t_state + LEFT_BORDER_T_STATES) % T_STATES_PER_FRAME;
+        self.render_pos = Vec2::new(
+            ((raster_t_state % T_STATES_PER_LINE) * 2 + self.second_half as u32) as u16,
+            (raster_t_state / T_STATES_PER_LINE) as u16
+        );
 
-            if self.render_pos.x >= BORDER_AREA.x + BORDER_AREA.w {
-                self.render_pos.x = 0;
-                self.render_pos.y += 1;
+        // Both the border colour and the display bytes are latched once per 8 pixels (4 T-states)
+        if self.render_pos.x % 8 == 0 {
+            self.timex_mode_latch = self.timex_mode;
+            self.border_latch = match self.ulaplus.border_color(self.border) {
+                Some(grb) => ulaplus_pixel(grb),
+                // Hi-res borders are always the paper colour
+                None if self.timex_mode_latch & 0b111 == TIMEX_HI_RES => self.hires_colors().1,
+                None => color_index(self.border, false)
+            };
+        }
+
+        if self.inside(SCREEN_AREA.x, SCREEN_AREA.y, SCREEN_AREA.w, SCREEN_AREA.h, self.render_pos.x, self.render_pos.y, 1, 1) {
+            let x = self.render_pos.x - SCREEN_AREA.x;
+            let y = self.render_pos.y - SCREEN_AREA.y;
+
+            if x % 8 == 0 {
+                self.fetch_display(x, y);
+            }
+
+            if self.timex_mode_latch & 0b111 == TIMEX_HI_RES {
+                // Each 8 pixel cell holds 16 hi-res pixels, the first 8 from screen 0 and the rest from screen 1
+                let left = self.hires_pixel(2 * (x % 8));
+                let right = self.hires_pixel(2 * (x % 8) + 1);
+                self.draw_subpixels(left, right);
+            } else {
+                let color = if self.bitmap_latch & (0x80 >> (x % 8)) != 0 { self.ink_latch } else { self.paper_latch };
+                self.draw_pixel(color);
             }
+        } else if self.inside(self.visible.x, self.visible.y, self.visible.w, self.visible.h, self.render_pos.x, self.render_pos.y, 1, 1) {
+            self.draw_pixel(self.border_latch);
+        }
+
+        if self.render_pos.x == self.visible.x + self.visible.w - 1
+            && self.inside(self.visible.x, self.visible.y, self.visible.w, self.visible.h, self.render_pos.x, self.render_pos.y, 1, 1) {
+            self.finish_line();
+        }
 
-            if self.render_pos.y >= BORDER_AREA.y + BORDER_AREA.h {
-                self.render_pos.y = 0;
+        self.second_half = !self.second_half;
+        if !self.second_half {
+            self.t_state += 1;
+
+            self.sample_acc += SAMPLE_RATE;
+            if self.sample_acc >= T_STATES_PER_SECOND {
+                self.sample_acc -= T_STATES_PER_SECOND;
+                let sample = self.speaker_level();
+                self.frame.samples.push(sample);
             }
+        }
+
+        if self.t_state >= T_STATES_PER_FRAME {
+            self.t_state = 0;
+            self.frame_count += 1;
 
-            if self.render_pos == Vec2::new(0, 0) {
-                self.video_layer.as_ref().unwrap().lock().expect("Couldn't unlock write lock for canvas").canvas.lock().unwrap()
-                    .present();
-                self.video_layer.as_ref().unwrap().lock().expect("Couldn't unlock write lock for canvas").canvas.lock().unwrap()
-                    .set_draw_color(self.convert_color(0));
-                self.video_layer.as_ref().unwrap().lock().expect("Couldn't unlock write lock for canvas").canvas.lock().unwrap()
-                    .clear();
+            let next = Frame::new(self.visible.w as usize * self.frame_scale, self.visible.h as usize * self.frame_scale, self.frame_count);
+            let frame = std::mem::replace(&mut self.frame, next);
+            // A frontend that has gone away doesn't stop the ULA, it carries on until the clock does
+            if self.config.wait_for_frames {
+                self.send_frame(frame);
+            } else {
+                let _ = self.frame_tx.try_send(frame);
             }
+        }
+    }
+
+    /// Waits for room for `frame`, answering the bus in the meantime so reads of port 0xFE don't stall the CPU
+    fn send_frame(&mut self, frame: Frame) {
+        loop {
+            let msg = {
+                let mut select = Select::new();
+                let send = select.send(&self.frame_tx);
+                select.recv(&self.bus_rx);
+
+                let op = select.select();
+                if op.index() == send {
+                    let _ = op.send(&self.frame_tx, frame);
+                    return;
+                }
+                op.recv(&self.bus_rx)
+            };
 
-            for event in self.video_layer.as_ref().unwrap().lock().expect("Couldn't unlock write lock for canvas").event_pump.lock().unwrap().poll_iter() {
-                match event {
-                    sdl2::event::Event::Quit {..} => self.clock_tx.send(ClockMessage::Stop).unwrap(),
-                    _ => {}
+            match msg {
+                Ok(msg) => self.handle_message(msg),
+                // Nothing left on the bus to answer
+                Err(_) => {
+                    let _ = self.frame_tx.send(frame);
+                    return;
                 }
             }
         }
     }
 
-    fn convert_color(&self, data: Byte) -> Color {
-        match data & 0b00000011 {
-            0 => Color::RGB(0x0,0x0,0x0),
-            1 => Color::RGB(0x0,0x0,0xd7),
-            2 => Color::RGB(0xd7,0x0,0x0),
-            3 => Color::RGB(0xd7,0x0,0xd7),
-            4 => Color::RGB(0x0,0xd7,0x0),
-            5 => Color::RGB(0x0,0xd7,0xd7),
-            6 => Color::RGB(0xd7,0xd7,0x0),
-            7 => Color::RGB(0xd7,0xd7,0xd7),
-            _ => Color::RGB(0x0,0x0,0x0)
+    fn speaker_level(&self) -> i16 {
+        let ear = if self.speaker & 0b00010000 != 0 { 0x2000 } else { -0x2000 };
+        let mic = if self.speaker & 0b00001000 != 0 { 0x0400 } else { -0x0400 };
+        ear + mic
+    }
+
+    fn draw_pixel(&mut self, color: Pixel) {
+        self.draw_subpixels(color, color);
+    }
+
+    /// Draws the left and right halves of the current pixel, only a Timex ULA's frames are wide enough to show both
+    fn draw_subpixels(&mut self, left: Pixel, right: Pixel) {
+        let x = (self.render_pos.x - self.visible.x) as usize * self.frame_scale;
+
+        self.line[x] = left;
+        if self.frame_scale > 1 {
+            self.line[x + 1] = right;
+        }
+    }
+
+    /// Copies the finished line into the frame, as many rows as it takes to keep the pixels square
+    fn finish_line(&mut self) {
+        let y = (self.render_pos.y - self.visible.y) as usize * self.frame_scale;
+
+        for row in y..y + self.frame_scale {
+            self.frame.set_line(row, &self.line);
+        }
+    }
+
+    /// Latches the bitmap and colours for the 8 pixel cell starting at display pixel (x, y)
+    fn fetch_display(&mut self, x: u16, y: u16) {
+        let bitmap = Self::bitmap_address(x, y);
+        // The second byte of each pair is fetched on the next T-state
+        let (first, second) = (self.t_state, self.t_state + 1);
+
+        match self.timex_mode_latch & 0b111 {
+            m if m == TIMEX_SCREEN_1 => {
+                self.bitmap_latch = self.read_ram(TIMEX_SCREEN_1_OFFSET + bitmap, first);
+                self.attribute_latch = self.read_ram(TIMEX_SCREEN_1_OFFSET + Self::attribute_address(x, y), second);
+            }
+            // Every bitmap byte gets its own attribute, laid out like the bitmap in the second display file
+            m if m == TIMEX_HI_COLOUR => {
+                self.bitmap_latch = self.read_ram(bitmap, first);
+                self.attribute_latch = self.read_ram(TIMEX_SCREEN_1_OFFSET + bitmap, second);
+            }
+            m if m == TIMEX_HI_RES => {
+                self.bitmap_latch = self.read_ram(bitmap, first);
+                self.hires_latch = self.read_ram(TIMEX_SCREEN_1_OFFSET + bitmap, second);
+                (self.ink_latch, self.paper_latch) = self.hires_colors();
+                return;
+            }
+            _ => {
+                self.bitmap_latch = self.read_ram(bitmap, first);
+                self.attribute_latch = self.read_ram(Self::attribute_address(x, y), second);
+            }
+        }
+
+        (self.ink_latch, self.paper_latch) = self.attribute_colors(self.attribute_latch);
+    }
+
+    /// Hi-res INK comes from bits 3-5 of port 0xFF, PAPER is its complement
+    fn hires_colors(&self) -> (Pixel, Pixel) {
+        let ink = (self.timex_mode_latch >> 3) & 0b111;
+        (color_index(ink, true), color_index(!ink, true))
+    }
+
+    /// Pixel `index` (0-15) of the current hi-res cell
+    fn hires_pixel(&self, index: u16) -> Pixel {
+        let byte = if index < 8 { self.bitmap_latch } else { self.hires_latch };
+        if byte & (0x80 >> (index % 8)) != 0 { self.ink_latch } else { self.paper_latch }
+    }
+
+    /// Offset of the bitmap byte for display pixel (x, y), the thirds/character rows/pixel rows are interleaved
+    fn bitmap_address(x: u16, y: u16) -> Address {
+        ((y & 0xC0) << 5) | ((y & 0x07) << 8) | ((y & 0x38) << 2) | (x >> 3)
+    }
+
+    /// Offset of the attribute byte covering display pixel (x, y)
+    fn attribute_address(x: u16, y: u16) -> Address {
+        0x1800 + (y >> 3) * 32 + (x >> 3)
+    }
+
+    /// A fetch on the same T-state as a refresh of 0x4000 - 0x7FFF (I in that range) collides with it, and the
+    /// ULA ends up reading from R instead of its own low address byte, which is what shows up as snow
+    fn read_ram(&self, address: Address, t_state: u32) -> Byte {
+        let address = match self.refresh_line.at(t_state) {
+            Some((i, r)) if (0x40..0x80).contains(&i) => (address & 0xFF00) | r as Address,
+            _ => address
+        };
+
+        let (tx, rx) = bounded(1);
+        if self.ula_ram.send(BusMessage::MemGet(address, tx)).is_err() {
+            return 0xFF;
+        }
+        match rx.recv() {
+            Ok(BusMessage::MemReadOk(b)) => b,
+            _ => 0xFF
+        }
+    }
+
+    /// Decodes an attribute byte into its (INK, PAPER) colours, FLASH swaps them every 16 frames
+    ///
+    /// With the ULAplus palette on, FLASH and BRIGHT pick a CLUT instead
+    fn attribute_colors(&self, attribute: Byte) -> (Pixel, Pixel) {
+        if let Some((ink, paper)) = self.ulaplus.attribute_colors(attribute) {
+            return (ulaplus_pixel(ink), ulaplus_pixel(paper));
+        }
+
+        let bright = attribute & 0b01000000 != 0;
+        let ink = color_index(attribute, bright);
+        let paper = color_index(attribute >> 3, bright);
+
+        if attribute & 0b10000000 != 0 && self.frame_count & 0x10 != 0 {
+            (paper, ink)
+        } else {
+            (ink, paper)
         }
     }
 
     fn inside(&self, x1: u16, y1: u16, w1: u16, h1: u16,
               x2: u16, y2: u16, w2: u16, h2: u16) -> bool {
-        x2 > x1 && y2 > y1 && x2 + w2 < x1 + w1 && y2 + h2 < y1 + h1
+        x2 >= x1 && y2 >= y1 && x2 + w2 <= x1 + w1 && y2 + h2 <= y1 + h1
+    }
+
+    /// The Timex machines only decode the low byte of port 0xFF
+    fn timex_port() -> Port {
+        Port::new(0x00FF, 0x00FF)
     }
 
     fn check_message(&mut self) {
-        let msg = self.bus_rx.try_recv();
-        if msg.is_ok() {
-            match msg.unwrap() {
-                BusMessage::MemPut(_, _, s) => s.send(BusMessage::Err).unwrap(),
-                BusMessage::MemGet(_, s) => s.send(BusMessage::Err).unwrap(),
-                BusMessage::IOPut(_, b, s) => {
-                    #[cfg(feature = "trace-ula")]
-                        let _ = span!(Level::TRACE, "Write to ULA Registers").enter();
-                    self.border_color = self.convert_color(b);
-                    s.send(BusMessage::IOWriteOk).unwrap();
-                },
-                BusMessage::IOGet(_, s) => s.send(BusMessage::Err).unwrap(),
-                BusMessage::GetRanges(s) => {
-                    #[cfg(feature = "trace-ula")]
-                        let _ = span!(Level::TRACE, "Send ULA memory-mapped ranges").enter();
-                    s.send(BusMessage::RangesRet(vec![], vec![], vec![Range(0xFE, 0xFF)], vec![Range(0xFE, 0xFF)])).unwrap();
-                },
-                _ => {}
-            }
+        if let Ok(msg) = self.bus_rx.try_recv() {
+            self.handle_message(msg);
+        }
+    }
+
+    fn handle_message(&mut self, msg: BusMessage) {
+        match msg {
+            BusMessage::MemPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+            BusMessage::MemGet(_, s) => { let _ = s.send(BusMessage::Err); },
+            BusMessage::IOPut(a, b, s) if self.config.timex && Self::timex_port().matches(a) => {
+                self.timex_mode = b;
+                let _ = s.send(BusMessage::IOWriteOk);
+            },
+            BusMessage::IOGet(a, s) if self.config.timex && Self::timex_port().matches(a) => {
+                let _ = s.send(BusMessage::IOReadOk(self.timex_mode));
+            },
+            BusMessage::IOPut(_, b, s) => {
+                #[cfg(feature = "trace-ula")]
+                    let _ = span!(Level::TRACE, "Write to ULA Registers").enter();
+                self.border = b & 0b00000111;
+                self.speaker = b & 0b00011000;
+                let _ = s.send(BusMessage::IOWriteOk);
+            },
+            BusMessage::IOGet(a, s) => {
+                // Bits 5 and 7 float high, with no tape in EAR an issue 3 board reads back the EAR output
+                let ear = if self.speaker & 0b00010000 != 0 { 0b01000000 } else { 0 };
+                let keys = self.keyboard.read((a >> 8) as Byte);
+                let _ = s.send(BusMessage::IOReadOk(0b10100000 | ear | keys));
+            },
+            BusMessage::GetRanges(s) => {
+                #[cfg(feature = "trace-ula")]
+                    let _ = span!(Level::TRACE, "Send ULA memory-mapped ranges").enter();
+                let mut ports = vec![Port::new(0x0001, 0x0000)];
+                if self.config.timex {
+                    ports.push(Self::timex_port());
+                }
+                let _ = s.send(BusMessage::RangesRet(vec![], vec![], ports.clone(), ports));
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_rows_are_interleaved() {
+        let cases = [
+            // (x, y, offset)
+            (0, 0, 0x0000),
+            (8, 0, 0x0001),
+            (255, 0, 0x001F),
+            // Pixel lines within a character row are 256 bytes apart
+            (0, 1, 0x0100),
+            (0, 7, 0x0700),
+            // The next character row follows on from the first line of the one before
+            (0, 8, 0x0020),
+            (0, 15, 0x0720),
+            (0, 63, 0x07E0),
+            // Each third of the screen is its own 2K block
+            (0, 64, 0x0800),
+            (0, 128, 0x1000),
+            (255, 191, 0x17FF),
+        ];
+
+        for (x, y, offset) in cases {
+            assert_eq!(Ula::bitmap_address(x, y), offset, "pixel ({}, {})", x, y);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn attributes_are_one_per_cell() {
+        assert_eq!(Ula::attribute_address(0, 0), 0x1800);
+        assert_eq!(Ula::attribute_address(7, 7), 0x1800);
+        assert_eq!(Ula::attribute_address(8, 0), 0x1801);
+        assert_eq!(Ula::attribute_address(0, 8), 0x1820);
+        assert_eq!(Ula::attribute_address(0, 64), 0x1900);
+        assert_eq!(Ula::attribute_address(255, 191), 0x1AFF);
+    }
+}
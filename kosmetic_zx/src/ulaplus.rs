@@ -0,0 +1,197 @@
+use std::sync::{Arc, RwLock};
+use std::thread;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::bus::{BusMessage, Port};
+use crate::common::{Address, Byte};
+
+#[cfg(feature = "trace-ula")]
+use tracing::*;
+
+pub static REGISTER_PORT: Address = 0xBF3B;
+pub static DATA_PORT: Address = 0xFF3B;
+
+#[derive(Debug, Clone)]
+pub struct UlaPlusState {
+    /// Set by bit 0 of the mode register, the ULA ignores the palette while this is clear
+    pub enabled: bool,
+    /// GRB332 colours, four CLUTs of 8 INKs followed by 8 PAPERs
+    pub palette: [Byte; 64]
+}
+
+/// The ULAplus state shared between the I/O device and the ULA that draws with it
+#[derive(Debug, Clone)]
+pub struct UlaPlusPalette(Arc<RwLock<UlaPlusState>>);
+
+impl Default for UlaPlusPalette {
+    fn default() -> Self {
+        UlaPlusPalette(Arc::new(RwLock::new(UlaPlusState {
+            enabled: false,
+            palette: [0; 64]
+        })))
+    }
+}
+
+impl UlaPlusPalette {
+    pub fn read(&self) -> UlaPlusState {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.read().unwrap().enabled
+    }
+
+    /// The GRB332 (INK, PAPER) for an attribute, or `None` with the palette off
+    ///
+    /// FLASH and BRIGHT together pick one of the four CLUTs
+    pub fn attribute_colors(&self, attribute: Byte) -> Option<(Byte, Byte)> {
+        let state = self.0.read().unwrap();
+        if !state.enabled {
+            return None;
+        }
+
+        let clut = ((attribute >> 6) * 16) as usize;
+        Some((
+            state.palette[clut + (attribute & 0b111) as usize],
+            state.palette[clut + 8 + ((attribute >> 3) & 0b111) as usize]
+        ))
+    }
+
+    /// The border is drawn with the PAPER colours of the first CLUT
+    pub fn border_color(&self, border: Byte) -> Option<Byte> {
+        let state = self.0.read().unwrap();
+        if state.enabled {
+            Some(state.palette[8 + (border & 0b111) as usize])
+        } else {
+            None
+        }
+    }
+}
+
+/// The ULAplus register (0xBF3B) and data (0xFF3B) ports
+pub struct UlaPlus {
+    state: UlaPlusPalette,
+    register: Byte,
+    receiver: Receiver<BusMessage>
+}
+
+impl UlaPlus {
+    /// Give the `UlaPlusPalette` to the ULA, and the `Sender` to the bus
+    pub fn new() -> (Sender<BusMessage>, UlaPlusPalette) {
+        let (tx, rx) = bounded(128);
+        let state = UlaPlusPalette::default();
+        let device_state = state.clone();
+
+        thread::spawn(move || {
+            let mut ulaplus = UlaPlus {
+                state: device_state,
+                register: 0,
+                receiver: rx
+            };
+
+            ulaplus.message_loop();
+        });
+
+        (tx, state)
+    }
+
+    fn message_loop(&mut self) {
+        while let Ok(msg) = self.receiver.recv() {
+            match msg {
+                BusMessage::MemPut(_, _, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::MemGet(_, s) => { let _ = s.send(BusMessage::Err); },
+                BusMessage::IOPut(a, b, s) => {
+                    #[cfg(feature = "trace-ula")]
+                        let _ = span!(Level::TRACE, "Write to ULAplus").enter();
+                    if a == REGISTER_PORT {
+                        self.register = b;
+                    } else {
+                        let mut state = self.state.0.write().unwrap();
+                        // Bits 6-7 of the register select the group, palette or mode
+                        match self.register & 0b11000000 {
+                            0b00000000 => state.palette[(self.register & 0b00111111) as usize] = b,
+                            0b01000000 => state.enabled = b & 0b00000001 != 0,
+                            _ => {}
+                        }
+                    }
+                    let _ = s.send(BusMessage::IOWriteOk);
+                },
+                BusMessage::IOGet(_, s) => {
+                    // Only the data port can be read back, it returns whatever the register points at
+                    let state = self.state.0.read().unwrap();
+                    let b = match self.register & 0b11000000 {
+                        0b00000000 => state.palette[(self.register & 0b00111111) as usize],
+                        0b01000000 => state.enabled as Byte,
+                        _ => 0xFF
+                    };
+                    let _ = s.send(BusMessage::IOReadOk(b));
+                },
+                BusMessage::GetRanges(s) => {
+                    #[cfg(feature = "trace-ula")]
+                        let _ = span!(Level::TRACE, "Send ULAplus ports").enter();
+                    let _ = s.send(BusMessage::RangesRet(vec![], vec![], vec![Port::exact(DATA_PORT)], vec![Port::exact(REGISTER_PORT), Port::exact(DATA_PORT)]));
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Expands a GRB332 colour to RGB888, the missing low blue bit is the OR of the other two
+pub fn grb332_to_rgb(grb: Byte) -> [u8; 3] {
+    let expand = |c: u8| (c << 5) | (c << 2) | (c >> 1);
+    let g = (grb >> 5) & 0b111;
+    let r = (grb >> 2) & 0b111;
+    let b = grb & 0b11;
+    let b = (b << 1) | (b != 0) as u8;
+    [expand(r), expand(g), expand(b)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn io(bus: &Sender<BusMessage>, message: impl FnOnce(Sender<BusMessage>) -> BusMessage) -> BusMessage {
+        let (tx, rx) = bounded(1);
+        bus.send(message(tx)).unwrap();
+        rx.recv().unwrap()
+    }
+
+    fn ulaplus_on_bus() -> (Sender<BusMessage>, UlaPlusPalette) {
+        let bus = Bus::new();
+        let (device, palette) = UlaPlus::new();
+        assert!(matches!(io(&bus, |s| BusMessage::AddDevice(device, s)), BusMessage::AddDeviceOk));
+        (bus, palette)
+    }
+
+    #[test]
+    fn mode_group_turns_the_palette_on() {
+        let (bus, palette) = ulaplus_on_bus();
+
+        assert!(matches!(io(&bus, |s| BusMessage::IOPut(REGISTER_PORT, 0b01000000, s)), BusMessage::IOWriteOk));
+        assert!(matches!(io(&bus, |s| BusMessage::IOPut(DATA_PORT, 1, s)), BusMessage::IOWriteOk));
+        assert!(palette.is_enabled());
+        assert!(matches!(io(&bus, |s| BusMessage::IOGet(DATA_PORT, s)), BusMessage::IOReadOk(1)));
+    }
+
+    #[test]
+    fn palette_entries_are_written_and_read_through_the_data_port() {
+        let (bus, palette) = ulaplus_on_bus();
+
+        io(&bus, |s| BusMessage::IOPut(REGISTER_PORT, 13, s));
+        io(&bus, |s| BusMessage::IOPut(DATA_PORT, 0xE3, s));
+        assert_eq!(palette.read().palette[13], 0xE3);
+        assert!(matches!(io(&bus, |s| BusMessage::IOGet(DATA_PORT, s)), BusMessage::IOReadOk(0xE3)));
+    }
+
+    #[test]
+    fn only_the_full_port_addresses_are_decoded() {
+        let (bus, palette) = ulaplus_on_bus();
+
+        // The register port can't be read, and near misses on either port belong to nobody
+        assert!(matches!(io(&bus, |s| BusMessage::IOGet(REGISTER_PORT, s)), BusMessage::Err));
+        assert!(matches!(io(&bus, |s| BusMessage::IOPut(0xBF3F, 0b01000000, s)), BusMessage::Err));
+        assert!(matches!(io(&bus, |s| BusMessage::IOPut(0x7F3B, 1, s)), BusMessage::Err));
+        assert!(!palette.is_enabled());
+    }
+}
@@ -1,32 +1,144 @@
 use std::sync::{Mutex, Arc};
-use sdl2::{AudioSubsystem, EventPump, Sdl, VideoSubsystem};
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::{AudioSubsystem, EventPump, GameControllerSubsystem, Sdl, VideoSubsystem};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use crate::frame::Image;
+
+/// Width over height of a pixel on a PAL TV, the 7.375MHz square pixel rate over the ULA's 7MHz pixel clock
+static PIXEL_ASPECT: (u32, u32) = (59, 56);
+
+#[derive(Debug, Clone)]
+pub struct DisplayConfig {
+    /// Initial window size as a multiple of the frame size, 2 to 6
+    pub scale: u32,
+    pub fullscreen: bool,
+    /// Stretch the pixels to the 59:56 shape a PAL TV shows them, rather than square. This is the pixel aspect
+    /// only, the border still decides the shape of the whole picture
+    pub pal_aspect: bool
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            scale: 2,
+            fullscreen: false,
+            pal_aspect: false
+        }
+    }
+}
 
 pub struct VideoLayer {
     pub ctx: Arc<Mutex<Sdl>>,
     pub vid_sub_sys: Arc<Mutex<VideoSubsystem>>,
     pub audio_sub_sys: Arc<Mutex<AudioSubsystem>>,
+    pub controller_sub_sys: Arc<Mutex<GameControllerSubsystem>>,
     pub canvas: Arc<Mutex<Canvas<Window>>>,
-    pub event_pump: Arc<Mutex<EventPump>>
+    pub event_pump: Arc<Mutex<EventPump>>,
+    config: DisplayConfig,
+    frame_size: (u32, u32),
+    texture_creator: TextureCreator<WindowContext>,
+    /// Streamed into every frame, only recreated when the frame size changes
+    texture: Texture
 }
 
 impl VideoLayer {
-    pub fn new() -> Arc<Mutex<VideoLayer>> {
+    pub fn new(width: u32, height: u32, config: DisplayConfig) -> VideoLayer {
         let ctx = sdl2::init().expect("Couldn't init SDL");
         let vid_sub_sys = ctx.video().expect("Couldn't get SDL VideoSubsystem");
         let audio_sub_sys = ctx.audio().expect("Couldn't get SDL AudioSubsystem");
-        let window = vid_sub_sys.window("KosmeticZX", 352, 315)
-            .position_centered()
-            .build().expect("Couldn't build window");
+        let controller_sub_sys = ctx.game_controller().expect("Couldn't get SDL GameControllerSubsystem");
+
+        // Keep pixels sharp when scaling up
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+
+        let (logical_w, logical_h) = Self::logical_size(&config, width, height);
+        let mut window = vid_sub_sys.window("KosmeticZX", logical_w * config.scale, logical_h * config.scale);
+        window.position_centered().resizable();
+        if config.fullscreen {
+            window.fullscreen_desktop();
+        }
+        let window = window.build().expect("Couldn't build window");
         let event_pump = ctx.event_pump().expect("Couldn't get event pump");
 
-        Arc::new(Mutex::new(VideoLayer {
+        let mut canvas = window.into_canvas().accelerated().build().expect("Couldn't build canvas");
+        // The renderer letterboxes anything that doesn't match the logical size's aspect ratio, and only scales by
+        // whole multiples so a resized window doesn't end up with uneven pixel widths. With the PAL aspect the
+        // columns are still stretched by 59/56, so some are a screen pixel wider than others
+        canvas.set_logical_size(logical_w, logical_h).expect("Couldn't set canvas logical size");
+        canvas.set_integer_scale(true).expect("Couldn't set canvas integer scaling");
+
+        let texture_creator = canvas.texture_creator();
+        let texture = Self::create_texture(&texture_creator, width, height);
+
+        VideoLayer {
             ctx: Arc::new(Mutex::new(ctx)),
             vid_sub_sys: Arc::new(Mutex::new(vid_sub_sys)),
             audio_sub_sys: Arc::new(Mutex::new(audio_sub_sys)),
-            canvas: Arc::new(Mutex::new(window.into_canvas().accelerated().build().expect("Couldn't build canvas"))),
-            event_pump: Arc::new(Mutex::new(event_pump))
-        }))
+            controller_sub_sys: Arc::new(Mutex::new(controller_sub_sys)),
+            canvas: Arc::new(Mutex::new(canvas)),
+            event_pump: Arc::new(Mutex::new(event_pump)),
+            config,
+            frame_size: (width, height),
+            texture_creator,
+            texture
+        }
+    }
+
+    fn create_texture(texture_creator: &TextureCreator<WindowContext>, width: u32, height: u32) -> Texture {
+        texture_creator.create_texture_streaming(PixelFormatEnum::ABGR8888, width, height)
+            .expect("Couldn't create frame texture")
+    }
+
+    fn logical_size(config: &DisplayConfig, width: u32, height: u32) -> (u32, u32) {
+        // Timex frames and the upscaling filters grow both sides alike, so the pixels keep the same shape
+        if config.pal_aspect {
+            (width * PIXEL_ASPECT.0 / PIXEL_ASPECT.1, height)
+        } else {
+            (width, height)
+        }
     }
-}
\ No newline at end of file
+
+    pub fn toggle_fullscreen(&mut self) {
+        self.config.fullscreen = !self.config.fullscreen;
+        let mode = if self.config.fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.lock().unwrap().window_mut().set_fullscreen(mode).expect("Couldn't change fullscreen mode");
+    }
+
+    /// Hides the pointer and reports relative motion only, so the host pointer can't leave the window
+    pub fn set_mouse_captured(&mut self, captured: bool) {
+        self.ctx.lock().unwrap().mouse().set_relative_mouse_mode(captured);
+    }
+
+    pub fn is_mouse_captured(&self) -> bool {
+        self.ctx.lock().unwrap().mouse().relative_mouse_mode()
+    }
+
+    /// Shows a converted (and maybe filtered) frame from the ULA in the window
+    pub fn present(&mut self, frame: &Image) {
+        let mut canvas = self.canvas.lock().unwrap();
+
+        let frame_size = (frame.width as u32, frame.height as u32);
+        if frame_size != self.frame_size {
+            let (logical_w, logical_h) = Self::logical_size(&self.config, frame_size.0, frame_size.1);
+            canvas.set_logical_size(logical_w, logical_h).expect("Couldn't set canvas logical size");
+            self.frame_size = frame_size;
+
+            let old = std::mem::replace(&mut self.texture, Self::create_texture(&self.texture_creator, frame_size.0, frame_size.1));
+            // Safe as nothing else holds the old texture and the renderer is still alive
+            unsafe { old.destroy(); }
+        }
+
+        let row_length = frame.width * 4;
+        self.texture.with_lock(None, |pixels, pitch| {
+            for (y, row) in frame.rgba.chunks_exact(row_length).enumerate() {
+                pixels[y * pitch..y * pitch + row_length].copy_from_slice(row);
+            }
+        }).expect("Couldn't upload frame");
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.copy(&self.texture, None, None).expect("Couldn't copy frame to canvas");
+        canvas.present();
+    }
+}